@@ -1,2 +1,6 @@
 pub mod apply;
+pub mod history;
 pub mod lint;
+pub mod lock;
+pub mod rollback;
+pub mod status;
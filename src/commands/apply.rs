@@ -1,54 +1,473 @@
+mod combine;
+mod confirm;
+mod plan;
+
+use std::collections::{HashMap, HashSet};
+
 use clap::Parser;
-use miette::Result;
+use miette::{IntoDiagnostic, Report, Result};
 use owo_colors::OwoColorize;
-use tracing::{info, instrument};
+use tokio::{signal, task::JoinSet};
+use tracing::{Instrument, info, info_span, instrument, warn};
+use tracing_indicatif::span_ext::IndicatifSpanExt;
 
 use crate::{
     GlobalArgs,
-    config::{Group, read_config},
-    filter::{check_condition, get_system_info},
-    package_managers::PackageManagers,
+    commands::lint::check_packages,
+    config::{Group, Hooks, read_config},
+    errors::{
+        ApplyFailed, CombinedInstallFailed, Interrupted, ProfileNotLocked, VerificationFailed,
+    },
+    filter::matching_groups,
+    hooks::run_hooks,
+    lockfile::{self, Lockfile},
+    ordering::sort_groups,
+    package_managers::{Context, PackageManagerConfig, PackageManagers},
+    state::StateFile,
+    summary::{Outcome, Summary},
+    transactions::{self, Change, Transaction},
 };
 
+use combine::{Preinstall, install_combined};
+use confirm::confirm;
+use plan::Plan;
+
 #[derive(Parser, Debug, Clone)]
 pub struct ApplyArgs {
     /// Perform a dry run without actually modifying anything on your system
     #[arg(long)]
     pub dry_run: bool,
+
+    /// Keep installing other groups after a failure and report every failure at the end
+    #[arg(short = 'k', long)]
+    pub keep_going: bool,
+
+    /// Fail if packages deviate from the lockfile, before installing for versions known up front
+    /// and after installing for the rest. Dry runs only report deviations
+    #[arg(long)]
+    pub locked: bool,
+}
+
+/// A batch with something to do, installed by a lane.
+struct LaneStep {
+    hooks: Hooks,
+
+    /// Missing packages, `None` if they were installed ahead of time.
+    missing: Option<PackageManagerConfig>,
+    config: PackageManagerConfig,
+    count: usize,
+    remarks: Vec<String>,
+}
+
+type Lane = Vec<LaneStep>;
+
+/// Installs `config`, logging what changed so that `rollback` can revert it.
+///
+/// Changes are logged even if installing fails, as some packages may be installed already.
+async fn install_logged(
+    managers: &PackageManagers,
+    state: &StateFile,
+    groups: Vec<String>,
+    config: PackageManagerConfig,
+) -> Result<()> {
+    // Batches the plan failed to check weren't verified up front
+    managers.verify(&config).await?;
+
+    let before = managers.snapshot(&config).await;
+    let result = managers.install(config.clone()).await;
+
+    let changes = match before {
+        Ok(before) => logged_changes(managers, &config, before).await,
+        Err(error) => Err(error),
+    };
+    let transaction = match changes {
+        Ok(Some(changes)) => Transaction {
+            groups,
+            changes,
+            irreversible: Vec::new(),
+            rolled_back: false,
+            config,
+        },
+        changes => {
+            // Package managers that can't snapshot opt out quietly, `rollback` lists what it can't undo
+            if let Err(error) = changes {
+                warn!("Changes to {config} packages can't be rolled back: {error}");
+            }
+            let irreversible = if result.is_ok() {
+                managers.packages(&config)
+            } else {
+                Vec::new()
+            };
+            Transaction {
+                groups,
+                changes: Vec::new(),
+                irreversible,
+                rolled_back: false,
+                config,
+            }
+        }
+    };
+    state.log(transaction).await;
+
+    result
 }
 
-#[instrument(skip(managers))]
-async fn install_group(managers: &PackageManagers, group: Group) -> Result<()> {
-    info!(
-        "Installing {}",
-        group.name.unwrap_or("untitled".to_string()).blue().bold()
-    );
-    for batch in group.packages {
-        // info!("Installing {} packages", batch.blue().bold());
-        managers.install_missing(batch).await?;
+/// Changes installing `config` made since the `before` snapshot, `None` if the package manager can't tell.
+async fn logged_changes(
+    managers: &PackageManagers,
+    config: &PackageManagerConfig,
+    before: Option<HashMap<String, String>>,
+) -> Result<Option<Vec<Change>>> {
+    let (Some(before), Some(after)) = (before, managers.snapshot(config).await?) else {
+        return Ok(None);
+    };
+
+    let mut changes = transactions::changes(&before, &after);
+    if let Some(affected) = managers.affected(config).await? {
+        changes.retain(|change| affected.contains(change.package()));
     }
-    Ok(())
+    Ok(Some(changes))
 }
 
-pub async fn apply(
+/// Just installed packages of `missing` with their versions, recorded in the state file.
+async fn installed_versions(
+    managers: &PackageManagers,
+    missing: &PackageManagerConfig,
+) -> Vec<(String, Option<String>)> {
+    // Versions are informational, so failing to look them up only leaves them out
+    match managers.installed_versions(missing).await {
+        Ok(versions) => versions,
+        Err(_) => managers
+            .packages(missing)
+            .into_iter()
+            .map(|name| (name, None))
+            .collect(),
+    }
+}
+
+/// Installs batches of a single package manager one after another,
+/// stopping at the first failure.
+async fn install_lane(
     managers: PackageManagers,
-    global_args: GlobalArgs,
-    args: ApplyArgs,
+    lane: Lane,
+    state: StateFile,
+    group: String,
+) -> Vec<(String, Result<Outcome>)> {
+    let mut results = Vec::new();
+
+    for LaneStep {
+        hooks,
+        missing,
+        config,
+        count,
+        remarks,
+    } in lane
+    {
+        let step = config.to_string();
+
+        let result = async {
+            run_hooks(hooks.before.as_ref()).await?;
+            if let Some(missing) = missing {
+                info!(
+                    "Found {} missing {} packages",
+                    count.blue().bold(),
+                    step.blue().bold()
+                );
+                install_logged(&managers, &state, vec![group.clone()], missing.clone()).await?;
+                let installed = installed_versions(&managers, &missing).await;
+                state.record(&step, &group, installed).await;
+            }
+            // Re-marked packages were installed before, so they are not recorded as installed
+            if !remarks.is_empty() {
+                managers.remark(&config, &remarks).await?;
+            }
+            run_hooks(hooks.after.as_ref()).await?;
+            Ok(Outcome::Succeeded {
+                installed: count,
+                remarked: remarks.len(),
+            })
+        }
+        .await;
+
+        let failed = result.is_err();
+        results.push((step, result));
+        if failed {
+            break;
+        }
+    }
+
+    results
+}
+
+#[instrument(skip(managers, preinstalled, state, summary))]
+async fn install_group(
+    managers: &PackageManagers,
+    group: &Group,
+    preinstalled: Option<&HashMap<usize, Preinstall>>,
+    state: &StateFile,
+    summary: &mut Summary,
 ) -> Result<()> {
+    let name = group.display_name();
+    info!("Installing {}", name.blue().bold());
+
+    // Batches are split into lanes by package manager. Lanes run concurrently,
+    // while batches within a lane run in order, as package managers lock their databases.
+    let mut lanes: Vec<Lane> = Vec::new();
+    let mut installed_any = false;
+    for (index, batch) in group.packages.iter().enumerate() {
+        let step = batch.config.to_string();
+        let remarks = summary.track(name, &step, managers.find_remarks(&batch.config).await)?;
+
+        let (missing, count) = match preinstalled.and_then(|batches| batches.get(&index)) {
+            Some(Preinstall::Installed { count }) if remarks.is_empty() => {
+                let installed = *count;
+                let outcome = Outcome::Succeeded {
+                    installed,
+                    remarked: 0,
+                };
+                summary.record(name, &step, outcome);
+                installed_any = true;
+                continue;
+            }
+            Some(Preinstall::Installed { count }) => (None, *count),
+            Some(Preinstall::Failed { code }) => {
+                let code = code.clone();
+                summary.record(name, &step, Outcome::Failed { code });
+                return Err(CombinedInstallFailed { manager: step }.into());
+            }
+            None => {
+                let (missing, count) =
+                    summary.track(name, &step, managers.find_missing(&batch.config).await)?;
+                ((count > 0).then_some(missing), count)
+            }
+        };
+        if missing.is_none() && remarks.is_empty() {
+            let outcome = Outcome::Succeeded {
+                installed: 0,
+                remarked: 0,
+            };
+            summary.record(name, &step, outcome);
+            continue;
+        }
+
+        let entry = LaneStep {
+            hooks: batch.hooks.clone(),
+            missing,
+            config: batch.config.clone(),
+            count,
+            remarks,
+        };
+        match lanes
+            .iter_mut()
+            .find(|lane| lane[0].config.manager() == entry.config.manager())
+        {
+            Some(lane) => lane.push(entry),
+            None => lanes.push(vec![entry]),
+        }
+    }
+
+    if lanes.is_empty() && !installed_any {
+        info!("All packages are already installed");
+        return Ok(());
+    }
+
+    summary.track(name, "hooks", run_hooks(group.hooks.before.as_ref()).await)?;
+
+    // Spinners would draw over interactive prompts, so they are only shown for concurrent lanes
+    let concurrent = lanes.len() > 1;
+    let mut tasks = JoinSet::new();
+    for lane in lanes {
+        let manager = lane[0].config.to_string();
+        let span = if concurrent {
+            info_span!("dotget::lane", indicatif.pb_show = true)
+        } else {
+            info_span!("dotget::lane")
+        };
+        span.pb_set_message(&format!("Installing {} packages", manager.bold()));
+
+        let lane = install_lane(managers.clone(), lane, state.clone(), name.to_string());
+        tasks.spawn(lane.instrument(span));
+    }
+
+    let mut first_error = None;
+    while let Some(results) = tasks.join_next().await {
+        for (step, result) in results.into_diagnostic()? {
+            match result {
+                Ok(outcome) => summary.record(name, &step, outcome),
+                Err(error) => {
+                    summary.fail(name, &step, &error);
+                    first_error.get_or_insert(error);
+                }
+            }
+        }
+    }
+
+    if let Some(error) = first_error {
+        return Err(error);
+    }
+
+    summary.track(name, "hooks", run_hooks(group.hooks.after.as_ref()).await)?;
+
+    Ok(())
+}
+
+async fn install_groups(
+    managers: &PackageManagers,
+    groups: &[Group],
+    state: &StateFile,
+    args: &ApplyArgs,
+) -> Result<()> {
+    let preinstalled = install_combined(managers, groups, state, args.keep_going).await?;
+
+    let mut summary = Summary::default();
+    // Names of groups that failed or were skipped, so that groups needing them are skipped too
+    let mut unavailable = HashSet::new();
+    let mut failed = 0;
+
+    for (index, group) in groups.iter().enumerate() {
+        let name = group.display_name();
+
+        if let Some(dependency) = group
+            .needs
+            .iter()
+            .flatten()
+            .find(|need| unavailable.contains(need.as_str()))
+        {
+            warn!(
+                "Skipping {} because {} was not installed",
+                name.blue().bold(),
+                dependency.blue().bold()
+            );
+            let reason = format!("needs {dependency}");
+            summary.record(name, "-", Outcome::Skipped { reason });
+            unavailable.insert(name.to_string());
+            continue;
+        }
+
+        if let Err(error) = install_group(
+            managers,
+            group,
+            preinstalled.get(&index),
+            state,
+            &mut summary,
+        )
+        .await
+        {
+            // Tampered downloads abort the run, even with `--keep-going` or in optional groups
+            let optional = group.optional == Some(true);
+            let tampered = error.downcast_ref::<VerificationFailed>().is_some();
+            if (!args.keep_going && !optional) || tampered {
+                return Err(error);
+            }
+
+            eprintln!("{error:?}");
+            if !optional {
+                failed += 1;
+            }
+            unavailable.insert(name.to_string());
+        }
+    }
+
+    if args.keep_going || summary.has_failures() {
+        summary.print();
+    }
+
+    if failed > 0 {
+        return Err(ApplyFailed { failed }.into());
+    }
+
+    Ok(())
+}
+
+pub async fn apply(global_args: GlobalArgs, args: ApplyArgs) -> Result<()> {
     let config = read_config(&global_args.file).await?;
+    let context = Context::new(&global_args, &config)?;
+    let managers = PackageManagers::new(context.clone())?;
 
-    let system = get_system_info()?;
+    let matching_groups = matching_groups(&config, &global_args)?;
+    let matching_groups = sort_groups(&config.groups, matching_groups)?;
 
-    let matching_groups = config.groups.into_iter().filter(|group| {
-        group
-            .conditions
+    let mut plan = Plan::new(&managers, &matching_groups).await?;
+
+    // Installed packages obviously exist, so only the missing ones are looked up
+    check_packages(&managers, plan.batches.iter().map(|batch| &batch.missing)).await?;
+
+    // Checked against what the run installs, so that packages it upgrades to their locked version pass
+    let lock = if args.locked {
+        let profile = lockfile::profile(&global_args)?;
+        let mut lockfile = Lockfile::read(&Lockfile::path(&global_args.file)).await?;
+        let Some(locked) = lockfile.profiles.remove(&profile) else {
+            return Err(ProfileNotLocked { profile }.into());
+        };
+
+        let (installed, _) = lockfile::resolve(&managers, &matching_groups).await?;
+        let missing = plan
+            .batches
             .iter()
-            .filter_map(|condition_name| config.conditions.get(condition_name))
-            .all(|c| check_condition(&system, c, &global_args))
-    });
+            .map(|batch| (&matching_groups[batch.group], &batch.missing));
+        let planned = lockfile::planned(&managers, installed, missing).await?;
+        if let Err(error) = lockfile::check(&profile, &locked, &planned) {
+            if !args.dry_run {
+                return Err(error.into());
+            }
+            eprintln!("{:?}", Report::from(error));
+        }
+        Some((profile, locked))
+    } else {
+        None
+    };
+
+    if args.dry_run {
+        if plan.is_empty() {
+            info!("All packages are already installed");
+        } else {
+            plan.print(&managers, &matching_groups);
+        }
+        return Ok(());
+    }
+
+    let mut matching_groups = matching_groups;
+    if context.interactive && !plan.is_empty() {
+        plan.print(&managers, &matching_groups);
+        let selected = confirm(&plan, &matching_groups).await?;
+
+        plan.retain(&selected);
+        matching_groups = matching_groups
+            .into_iter()
+            .zip(selected)
+            .filter_map(|(group, selected)| selected.then_some(group))
+            .collect();
+    }
+
+    // Downloads are verified up front, so that nothing is installed if any of them was tampered with
+    for batch in &plan.batches {
+        managers.verify(&batch.missing).await?;
+    }
+
+    let state = StateFile::open(&global_args.file).await?;
+
+    // Dropping the guard stops refreshing credentials, also when interrupted below
+    let _keep_alive = if plan.needs_root(&managers) {
+        context
+            .privileges
+            .acquire(context.runner.clone(), context.interactive)
+            .await?
+    } else {
+        None
+    };
+
+    // Repositories shared by batches of different groups are set up before any of them installs
+    plan.prepare(&managers).await?;
+
+    tokio::select! {
+        result = install_groups(&managers, &matching_groups, &state, &args) => result,
+        _ = signal::ctrl_c() => Err(Interrupted.into()),
+    }?;
 
-    for group in matching_groups {
-        install_group(&managers, group).await?;
+    // Versions only known once installed, e.g. of AUR packages, are checked now
+    if let Some((profile, locked)) = lock {
+        let (installed, _) = lockfile::resolve(&managers, &matching_groups).await?;
+        lockfile::check(&profile, &locked, &installed)?;
     }
 
     Ok(())
@@ -0,0 +1,191 @@
+use std::collections::{HashMap, HashSet};
+
+use miette::Result;
+use owo_colors::OwoColorize;
+use tracing::{info, info_span};
+
+use super::{install_logged, installed_versions};
+use crate::{
+    config::Group,
+    errors::VerificationFailed,
+    package_managers::{PackageManagerConfig, PackageManagers},
+    state::StateFile,
+};
+
+/// Outcome of a batch installed ahead of time.
+pub enum Preinstall {
+    Installed {
+        count: usize,
+    },
+
+    /// The shared transaction failed, with the error code of the failure.
+    Failed {
+        code: String,
+    },
+}
+
+/// Batches installed ahead of time, keyed by group index and then by batch index.
+pub type Preinstalled = HashMap<usize, HashMap<usize, Preinstall>>;
+
+struct Contribution {
+    group: usize,
+    batch: usize,
+    count: usize,
+
+    /// Missing packages of the batch, recorded as installed by its group.
+    missing: PackageManagerConfig,
+}
+
+struct Transaction {
+    config: PackageManagerConfig,
+    contributions: Vec<Contribution>,
+}
+
+/// Installs missing packages shared by multiple groups in as few transactions as possible,
+/// so that e.g. pacman asks for confirmation once instead of once per group.
+///
+/// Batches with hooks, and groups that have to wait for hooks of other groups,
+/// are left to be installed with their group.
+///
+/// A failed transaction fails every contributing group. As with groups installed on their own,
+/// it aborts the run unless all of them are optional or `keep_going` is set.
+pub async fn install_combined(
+    managers: &PackageManagers,
+    groups: &[Group],
+    state: &StateFile,
+    keep_going: bool,
+) -> Result<Preinstalled> {
+    let mut transactions: Vec<Transaction> = Vec::new();
+    // Names of groups that are only settled after their hooks run
+    let mut hooked = HashSet::new();
+
+    for (group_index, group) in groups.iter().enumerate() {
+        let blocked = group.hooks.before.is_some()
+            || group
+                .needs
+                .iter()
+                .flatten()
+                .any(|need| hooked.contains(need.as_str()));
+        let has_hooks = group.hooks.after.is_some()
+            || group
+                .packages
+                .iter()
+                .any(|batch| batch.hooks.before.is_some() || batch.hooks.after.is_some());
+
+        if (blocked || has_hooks)
+            && let Some(name) = &group.name
+        {
+            hooked.insert(name.as_str());
+        }
+        if blocked {
+            continue;
+        }
+
+        for (batch_index, batch) in group.packages.iter().enumerate() {
+            if batch.hooks.before.is_some() || batch.hooks.after.is_some() {
+                continue;
+            }
+
+            // Failures are reported once the group installs this batch on its own
+            let Ok((missing, count)) = managers.find_missing(&batch.config).await else {
+                continue;
+            };
+            if count == 0 {
+                continue;
+            }
+
+            let contribution = Contribution {
+                group: group_index,
+                batch: batch_index,
+                count,
+                missing: missing.clone(),
+            };
+
+            match transactions
+                .iter_mut()
+                .position(|transaction| managers.merge(&mut transaction.config, &missing))
+            {
+                Some(index) => transactions[index].contributions.push(contribution),
+                None => transactions.push(Transaction {
+                    config: missing,
+                    contributions: vec![contribution],
+                }),
+            }
+        }
+    }
+
+    let mut preinstalled = Preinstalled::new();
+
+    // A transaction with a single contribution gains nothing from being installed early
+    for transaction in transactions
+        .into_iter()
+        .filter(|transaction| transaction.contributions.len() > 1)
+    {
+        let manager = transaction.config.to_string();
+        let count: usize = transaction.contributions.iter().map(|c| c.count).sum();
+        info!(
+            "Installing {} missing {} packages from {} groups at once",
+            count.blue().bold(),
+            manager.blue().bold(),
+            transaction.contributions.len().blue().bold()
+        );
+
+        {
+            let _nested = info_span!("dotget::nested").entered();
+            let separator = ", ".dimmed().to_string();
+            for contribution in &transaction.contributions {
+                info!(
+                    "{}: {}",
+                    groups[contribution.group].display_name().bold(),
+                    managers.packages(&contribution.missing).join(&separator)
+                );
+            }
+        }
+
+        let names = transaction
+            .contributions
+            .iter()
+            .map(|contribution| groups[contribution.group].display_name().to_string())
+            .collect();
+        match install_logged(managers, state, names, transaction.config.clone()).await {
+            Ok(()) => {
+                for contribution in transaction.contributions {
+                    let installed = installed_versions(managers, &contribution.missing).await;
+                    let group = groups[contribution.group].display_name();
+                    state.record(&manager, group, installed).await;
+
+                    preinstalled.entry(contribution.group).or_default().insert(
+                        contribution.batch,
+                        Preinstall::Installed {
+                            count: contribution.count,
+                        },
+                    );
+                }
+            }
+            Err(error) => {
+                // Tampered downloads abort the run, even with `--keep-going` or in optional groups
+                let tampered = error.downcast_ref::<VerificationFailed>().is_some();
+                let required = transaction
+                    .contributions
+                    .iter()
+                    .any(|contribution| groups[contribution.group].optional != Some(true));
+                if tampered || (required && !keep_going) {
+                    return Err(error);
+                }
+
+                eprintln!("{error:?}");
+                let code = error
+                    .code()
+                    .map_or_else(|| "unknown".to_string(), |code| code.to_string());
+                for contribution in transaction.contributions {
+                    preinstalled.entry(contribution.group).or_default().insert(
+                        contribution.batch,
+                        Preinstall::Failed { code: code.clone() },
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(preinstalled)
+}
@@ -0,0 +1,114 @@
+use std::io::Write;
+
+use miette::{IntoDiagnostic, Result};
+use owo_colors::OwoColorize;
+use tokio::io::{self, AsyncBufReadExt, BufReader, Lines, Stdin};
+use tracing::warn;
+
+use crate::{config::Group, errors::Cancelled};
+
+use super::plan::Plan;
+
+type Input = Lines<BufReader<Stdin>>;
+
+/// Asks whether to install the plan, letting the user deselect groups first.
+///
+/// Returns which of `groups` are selected.
+pub async fn confirm(plan: &Plan, groups: &[Group]) -> Result<Vec<bool>> {
+    let mut selected = vec![true; groups.len()];
+    let mut input = BufReader::new(io::stdin()).lines();
+
+    loop {
+        let answer = prompt(&mut input, "Proceed with installation? [y/N/e(dit)]").await?;
+        match answer.to_lowercase().as_str() {
+            "y" | "yes" => return Ok(selected),
+            "e" | "edit" => edit(&mut input, plan, groups, &mut selected).await?,
+            _ => return Err(Cancelled.into()),
+        }
+    }
+}
+
+/// Shows a checklist of groups with something to install until the user is done toggling them.
+async fn edit(
+    input: &mut Input,
+    plan: &Plan,
+    groups: &[Group],
+    selected: &mut [bool],
+) -> Result<()> {
+    let candidates = plan.groups();
+
+    loop {
+        println!();
+        for (number, &group) in candidates.iter().enumerate() {
+            let mark = if selected[group] {
+                "[x]".green().to_string()
+            } else {
+                "[ ]".dimmed().to_string()
+            };
+            println!(
+                "  {} {mark} {}",
+                format!("{:>2}", number + 1).dimmed(),
+                groups[group].display_name().bold()
+            );
+        }
+
+        let answer = prompt(
+            input,
+            "Toggle groups by number (e.g. `1 3`), enter when done:",
+        )
+        .await?;
+        if answer.is_empty() {
+            break;
+        }
+
+        for token in answer.split_whitespace() {
+            match token
+                .parse::<usize>()
+                .ok()
+                .and_then(|number| number.checked_sub(1))
+                .and_then(|index| candidates.get(index))
+            {
+                Some(&group) => selected[group] = !selected[group],
+                None => warn!("Ignoring {token}, not a group number"),
+            }
+        }
+    }
+
+    deselect_dependents(groups, selected);
+    Ok(())
+}
+
+/// Deselects groups that need a deselected group.
+///
+/// Groups are sorted so that dependencies come first, so a single pass is enough.
+fn deselect_dependents(groups: &[Group], selected: &mut [bool]) {
+    for index in 0..groups.len() {
+        if !selected[index] {
+            continue;
+        }
+
+        let deselected = |need: &&String| {
+            groups
+                .iter()
+                .zip(selected.iter())
+                .any(|(group, &selected)| !selected && group.name.as_ref() == Some(*need))
+        };
+        if let Some(dependency) = groups[index].needs.iter().flatten().find(deselected) {
+            warn!(
+                "Skipping {} because it needs {}",
+                groups[index].display_name().blue().bold(),
+                dependency.blue().bold()
+            );
+            selected[index] = false;
+        }
+    }
+}
+
+/// Reads a single trimmed line, treating a closed stdin as an empty answer.
+async fn prompt(input: &mut Input, question: &str) -> Result<String> {
+    print!("{} ", question.bold());
+    std::io::stdout().flush().into_diagnostic()?;
+
+    let line = input.next_line().await.into_diagnostic()?;
+    Ok(line.unwrap_or_default().trim().to_string())
+}
@@ -0,0 +1,160 @@
+use miette::Result;
+use owo_colors::OwoColorize;
+
+use crate::{
+    config::{Group, Hooks},
+    errors::VerificationFailed,
+    package_managers::{PackageManagerConfig, PackageManagers},
+};
+
+pub struct PlannedBatch {
+    pub group: usize,
+    pub hooks: Hooks,
+    pub missing: PackageManagerConfig,
+    pub count: usize,
+
+    /// Installed packages that are only re-marked, e.g. as explicitly installed.
+    pub remarks: Vec<String>,
+}
+
+/// Snapshot of missing packages, taken before anything is installed.
+pub struct Plan {
+    pub batches: Vec<PlannedBatch>,
+
+    /// Steps taken once before installing, e.g. adding repositories.
+    pub setup: Vec<String>,
+}
+
+impl Plan {
+    /// Batches that can't be checked are left out,
+    /// their errors are reported once their group is installed.
+    /// Failed verifications of downloads abort right away instead.
+    pub async fn new(managers: &PackageManagers, groups: &[Group]) -> Result<Self> {
+        let mut batches = Vec::new();
+
+        for (group, batch) in groups
+            .iter()
+            .enumerate()
+            .flat_map(|(index, group)| group.packages.iter().map(move |batch| (index, batch)))
+        {
+            let remarks = managers
+                .find_remarks(&batch.config)
+                .await
+                .unwrap_or_default();
+            match managers.find_missing(&batch.config).await {
+                Ok((missing, count)) if count > 0 || !remarks.is_empty() => {
+                    batches.push(PlannedBatch {
+                        group,
+                        hooks: batch.hooks.clone(),
+                        missing,
+                        count,
+                        remarks,
+                    });
+                }
+                Err(error) if error.downcast_ref::<VerificationFailed>().is_some() => {
+                    return Err(error);
+                }
+                _ => {}
+            }
+        }
+
+        let setup = managers.preparation(&Self::missing(&batches)).await?;
+
+        Ok(Self { batches, setup })
+    }
+
+    /// Runs the setup every remaining batch needs.
+    pub async fn prepare(&self, managers: &PackageManagers) -> Result<()> {
+        managers.prepare(&Self::missing(&self.batches)).await
+    }
+
+    /// Missing packages of batches that install anything.
+    fn missing(batches: &[PlannedBatch]) -> Vec<&PackageManagerConfig> {
+        batches
+            .iter()
+            .filter(|batch| batch.count > 0)
+            .map(|batch| &batch.missing)
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// Indices of groups that have something to install.
+    pub fn groups(&self) -> Vec<usize> {
+        let mut groups: Vec<usize> = self.batches.iter().map(|batch| batch.group).collect();
+        groups.dedup();
+        groups
+    }
+
+    /// Drops batches of groups that are not `selected`.
+    pub fn retain(&mut self, selected: &[bool]) {
+        self.batches.retain(|batch| selected[batch.group]);
+    }
+
+    pub fn needs_root(&self, managers: &PackageManagers) -> bool {
+        // Re-marking changes the package database, which is owned by root
+        self.batches
+            .iter()
+            .any(|batch| managers.needs_root(&batch.missing) || !batch.remarks.is_empty())
+    }
+
+    pub fn print(&self, managers: &PackageManagers, groups: &[Group]) {
+        println!("\n{}", "Plan".bold());
+
+        if !self.setup.is_empty() {
+            println!("  {}", "Setup".blue().bold());
+            print_commands("    ", &self.setup);
+        }
+
+        for group in self.groups() {
+            let hooks = &groups[group].hooks;
+            println!("  {}", groups[group].display_name().blue().bold());
+            print_commands("    ", hooks.before.iter().flatten());
+
+            for batch in self.batches.iter().filter(|batch| batch.group == group) {
+                let separator = ", ".dimmed().to_string();
+                let manager = batch.missing.to_string();
+                if batch.count > 0 {
+                    println!(
+                        "    {} {} {}",
+                        manager.bold(),
+                        format!("({})", batch.count).dimmed(),
+                        managers.packages(&batch.missing).join(&separator)
+                    );
+                }
+                if !batch.remarks.is_empty() {
+                    println!(
+                        "    {} {} {}",
+                        manager.bold(),
+                        format!("({} to re-mark)", batch.remarks.len()).dimmed(),
+                        batch.remarks.join(&separator)
+                    );
+                }
+                print_commands("      ", batch.hooks.before.iter().flatten());
+                if batch.count > 0 {
+                    print_commands("      ", &managers.commands(&batch.missing));
+                }
+                if !batch.remarks.is_empty() {
+                    let commands = managers.remark_commands(&batch.missing, &batch.remarks);
+                    print_commands("      ", &commands);
+                }
+                print_commands("      ", batch.hooks.after.iter().flatten());
+            }
+
+            print_commands("    ", hooks.after.iter().flatten());
+        }
+
+        if self.needs_root(managers) {
+            println!("\n  {}", "Root privileges are required".yellow());
+        }
+        println!();
+    }
+}
+
+fn print_commands<'a>(indent: &str, commands: impl IntoIterator<Item = &'a String>) {
+    for command in commands {
+        println!("{indent}{} {}", "$".dimmed(), command.dimmed());
+    }
+}
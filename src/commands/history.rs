@@ -0,0 +1,67 @@
+use clap::Parser;
+use miette::Result;
+use owo_colors::OwoColorize;
+use tracing::info;
+
+use crate::state::State;
+
+#[derive(Parser, Debug, Clone)]
+pub struct HistoryArgs {
+    /// Only show these packages, e.g. to find out which group installed them
+    pub packages: Vec<String>,
+}
+
+pub async fn history(args: HistoryArgs) -> Result<()> {
+    let Some(path) = State::default_path() else {
+        miette::bail!("Unable to find a state directory");
+    };
+    let state = State::read(&path).await?;
+
+    let mut shown = 0;
+    for (manager, packages) in &state.packages {
+        let mut packages: Vec<_> = packages
+            .iter()
+            .filter(|(name, _)| args.packages.is_empty() || args.packages.contains(name))
+            .collect();
+        if packages.is_empty() {
+            continue;
+        }
+        // Oldest first, so that the output reads like a log
+        packages.sort_by_key(|(_, package)| package.installed_at);
+
+        let name_width = packages
+            .iter()
+            .map(|(name, _)| name.chars().count())
+            .max()
+            .unwrap_or_default();
+        let version_width = packages
+            .iter()
+            .map(|(_, package)| package.version.as_deref().unwrap_or("-").chars().count())
+            .max()
+            .unwrap_or_default();
+
+        println!("{}", manager.blue().bold());
+        for (name, package) in &packages {
+            let installed_at = package.installed_at.with_timezone(&chrono::Local);
+            let version = package.version.as_deref().unwrap_or("-");
+            println!(
+                "  {}  {version:<version_width$}  {}  {} {}",
+                format!("{name:<name_width$}").bold(),
+                installed_at.format("%Y-%m-%d %H:%M").dimmed(),
+                package.group.blue(),
+                format!("({})", package.config.display()).dimmed()
+            );
+        }
+        shown += packages.len();
+    }
+
+    if shown == 0 {
+        if args.packages.is_empty() {
+            info!("Nothing was installed by chezpilot yet");
+        } else {
+            info!("None of these packages were installed by chezpilot");
+        }
+    }
+
+    Ok(())
+}
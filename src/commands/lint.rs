@@ -1,8 +1,42 @@
 use miette::Result;
+use tracing::info;
 
-use crate::{GlobalArgs, config::read_config, package_managers::PackageManagers};
+use crate::{
+    GlobalArgs,
+    config::read_config,
+    errors::InvalidPackages,
+    ordering::lint_groups,
+    package_managers::{Context, PackageManagerConfig, PackageManagers},
+    success,
+};
 
-pub async fn lint(managers: PackageManagers, global_args: GlobalArgs) -> Result<()> {
+/// Checks every package declared in `batches`, reporting all problems at once.
+pub async fn check_packages(
+    managers: &PackageManagers,
+    batches: impl IntoIterator<Item = &PackageManagerConfig>,
+) -> Result<()> {
+    let mut problems = Vec::new();
+    for batch in batches {
+        problems.extend(managers.validate(batch).await?);
+    }
+
+    if !problems.is_empty() {
+        return Err(InvalidPackages { problems }.into());
+    }
+
+    Ok(())
+}
+
+pub async fn lint(global_args: GlobalArgs) -> Result<()> {
     let config = read_config(&global_args.file).await?;
-    todo!()
+    let managers = PackageManagers::new(Context::new(&global_args, &config)?)?;
+
+    lint_groups(&config.groups, &config.conditions)?;
+
+    info!("Checking declared packages");
+    let batches = config.groups.iter().flat_map(|group| &group.packages);
+    check_packages(&managers, batches.map(|batch| &batch.config)).await?;
+
+    success!("No problems found");
+    Ok(())
 }
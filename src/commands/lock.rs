@@ -0,0 +1,46 @@
+use miette::Result;
+use owo_colors::OwoColorize;
+use tracing::{info, warn};
+
+use crate::{
+    GlobalArgs,
+    config::read_config,
+    filter::matching_groups,
+    lockfile::{self, Lockfile},
+    ordering::sort_groups,
+    package_managers::{Context, PackageManagers},
+    success,
+};
+
+pub async fn lock(global_args: GlobalArgs) -> Result<()> {
+    let config = read_config(&global_args.file).await?;
+    let managers = PackageManagers::new(Context::new(&global_args, &config)?)?;
+    let profile = lockfile::profile(&global_args)?;
+
+    let groups = matching_groups(&config, &global_args)?;
+    let groups = sort_groups(&config.groups, groups)?;
+
+    let (locked, unresolved) = lockfile::resolve(&managers, &groups).await?;
+    if !unresolved.is_empty() {
+        let separator = ", ".dimmed().to_string();
+        warn!(
+            "Left out {} packages without a known version: {}",
+            unresolved.len().bold(),
+            unresolved.join(&separator)
+        );
+    }
+
+    // Locks of other profiles are kept as they are
+    let path = Lockfile::path(&global_args.file);
+    let mut lockfile = if path.exists() {
+        Lockfile::read(&path).await?
+    } else {
+        info!("Creating {}", path.display().bold());
+        Lockfile::default()
+    };
+    lockfile.profiles.insert(profile.clone(), locked);
+    lockfile.write(&path).await?;
+
+    success!("Locked profile {}", profile.blue().bold());
+    Ok(())
+}
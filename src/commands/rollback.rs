@@ -0,0 +1,138 @@
+use std::io::Write;
+
+use chrono::{Local, Utc};
+use clap::Parser;
+use miette::{IntoDiagnostic, Result};
+use owo_colors::OwoColorize;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::{info, warn};
+
+use crate::{
+    GlobalArgs,
+    config::read_config,
+    errors::Cancelled,
+    package_managers::{Context, PackageManagers},
+    state::State,
+    success,
+    transactions::{Change, RunLog},
+};
+
+#[derive(Parser, Debug, Clone)]
+pub struct RollbackArgs {
+    /// Only show what would be rolled back
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+pub async fn rollback(global_args: GlobalArgs, args: RollbackArgs) -> Result<()> {
+    let Some(dir) = RunLog::dir() else {
+        miette::bail!("Unable to find a state directory");
+    };
+    let Some((path, mut run)) = RunLog::latest(&dir).await? else {
+        info!("No run installed anything yet");
+        return Ok(());
+    };
+
+    let started_at = run
+        .started_at
+        .with_timezone(&Local)
+        .format("%Y-%m-%d %H:%M");
+    if run.rolled_back_at.is_some() {
+        info!("The last run from {started_at} was rolled back already");
+        return Ok(());
+    }
+
+    println!("Rolling back the run from {}", started_at.bold());
+    for transaction in run
+        .transactions
+        .iter()
+        .rev()
+        .filter(|transaction| !transaction.rolled_back)
+    {
+        println!(
+            "{} {}",
+            transaction.groups.join(", ").blue().bold(),
+            format!("({})", transaction.config).dimmed()
+        );
+        for change in &transaction.changes {
+            match change {
+                Change::Installed { package, version } => {
+                    println!("  {} {package} {}", "remove".red(), version.dimmed());
+                }
+                Change::Upgraded { package, from, to } => println!(
+                    "  {} {package} {}",
+                    "downgrade".yellow(),
+                    format!("{to} → {from}").dimmed()
+                ),
+            }
+        }
+        for package in &transaction.irreversible {
+            println!(
+                "  {} {package} {}",
+                "keep".dimmed(),
+                "(can't be undone)".dimmed()
+            );
+        }
+    }
+
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let config = read_config(&global_args.file).await?;
+    let context = Context::new(&global_args, &config)?;
+    if context.interactive {
+        print!("{} ", "Proceed with rollback? [y/N]".bold());
+        std::io::stdout().flush().into_diagnostic()?;
+        let answer = BufReader::new(tokio::io::stdin())
+            .lines()
+            .next_line()
+            .await
+            .into_diagnostic()?
+            .unwrap_or_default();
+        if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            return Err(Cancelled.into());
+        }
+    }
+
+    // Reverted newest first, as later transactions may depend on earlier ones.
+    // Progress is recorded after each transaction, so a retry after a failure continues where it stopped.
+    let managers = PackageManagers::new(context)?;
+    let state_path = State::default_path();
+    for index in (0..run.transactions.len()).rev() {
+        let transaction = &run.transactions[index];
+        if transaction.rolled_back {
+            continue;
+        }
+        if !transaction.changes.is_empty() {
+            managers
+                .rollback(&transaction.config, &transaction.changes)
+                .await?;
+        }
+
+        run.transactions[index].rolled_back = true;
+        run.write(&path).await?;
+
+        // Removed packages are no longer installed by chezpilot
+        let transaction = &run.transactions[index];
+        if let Some(path) = &state_path {
+            let removed: Vec<&str> = transaction
+                .changes
+                .iter()
+                .filter(|change| matches!(change, Change::Installed { .. }))
+                .map(Change::package)
+                .collect();
+            let mut state = State::read(path).await?;
+            state.forget(&transaction.config.to_string(), &removed);
+            if let Err(error) = state.write(path).await {
+                warn!("Failed to write {}: {error}", path.display());
+            }
+        }
+    }
+
+    run.rolled_back_at = Some(Utc::now());
+    run.write(&path).await?;
+
+    success!("Rolled back the run from {started_at}");
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use miette::{Report, Result};
+use owo_colors::OwoColorize;
+
+use crate::{
+    GlobalArgs,
+    config::read_config,
+    errors::InvalidPackages,
+    filter::matching_groups,
+    ordering::sort_groups,
+    package_managers::{Context, PackageManagers},
+};
+
+pub async fn status(global_args: GlobalArgs) -> Result<()> {
+    let config = read_config(&global_args.file).await?;
+    let managers = PackageManagers::new(Context::new(&global_args, &config)?)?;
+
+    let groups = matching_groups(&config, &global_args)?;
+    let groups = sort_groups(&config.groups, groups)?;
+
+    let mut problems = Vec::new();
+    for group in &groups {
+        println!("{}", group.display_name().blue().bold());
+
+        for batch in &group.packages {
+            let manager = batch.config.to_string();
+            match managers.find_missing(&batch.config).await {
+                Ok((_, 0)) => println!("  {} {}", manager.bold(), "up to date".green()),
+                Ok((missing, count)) => {
+                    let separator = ", ".dimmed().to_string();
+                    println!(
+                        "  {} {} {}",
+                        manager.bold(),
+                        format!("{count} missing").yellow(),
+                        managers.packages(&missing).join(&separator)
+                    );
+                }
+                Err(error) => println!("  {} {}", manager.bold(), error.red()),
+            }
+
+            problems.extend(managers.validate(&batch.config).await?);
+        }
+    }
+
+    // Problems are only reported, `lint` and `apply` fail on them
+    if !problems.is_empty() {
+        eprintln!("\n{:?}", Report::from(InvalidPackages { problems }));
+    }
+
+    Ok(())
+}
@@ -9,10 +9,25 @@ use tokio::fs;
 use crate::{
     errors::{InvalidConfig, UnableToReadConfig},
     package_managers::PackageManagerConfig,
+    privilege::ElevationTool,
+    verify::Key,
 };
 
 #[derive(Deserialize, JsonSchema, Debug, Clone)]
 pub struct Config {
+    /// Tool used to run commands that need root, detected automatically if not set.
+    ///
+    /// Overridden by the `--elevation-tool` flag and the `ELEVATION_TOOL` environment variable.
+    pub elevation_tool: Option<ElevationTool>,
+
+    /// Public keys downloaded artifacts can be verified with, referenced by name as `key`.
+    ///
+    /// Examples:
+    /// - `release-signing: { minisign: "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3" }`
+    /// - `laptop: { ssh: "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIETtSY7MRPJtDyC1JD9gE/KUDPcsl61cwZl6LjH8qA3T" }`
+    #[serde(default)]
+    pub keys: HashMap<String, Key>,
+
     pub conditions: HashMap<String, Condition>,
     pub groups: Vec<Group>,
 }
@@ -117,7 +132,51 @@ pub struct Condition {
 pub struct Group {
     pub name: Option<String>,
     pub conditions: Vec<String>,
-    pub packages: Vec<PackageManagerConfig>,
+
+    /// Names of groups that have to be installed before this one.
+    ///
+    /// Every referenced group must exist and match the current system.
+    pub needs: Option<Vec<String>>,
+
+    /// If set to `true`, failures of this group never fail the whole run.
+    pub optional: Option<bool>,
+
+    #[serde(flatten)]
+    pub hooks: Hooks,
+
+    pub packages: Vec<Batch>,
+}
+
+/// A single package manager invocation inside a group.
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+pub struct Batch {
+    #[serde(flatten)]
+    pub config: PackageManagerConfig,
+
+    #[serde(flatten)]
+    pub hooks: Hooks,
+}
+
+/// Shell commands run around an installation step.
+///
+/// Hooks only run when the step actually installs something.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct Hooks {
+    /// Commands run before installing missing packages.
+    pub before: Option<Vec<String>>,
+
+    /// Commands run after missing packages were installed.
+    ///
+    /// Examples:
+    /// - `"systemctl --user enable --now syncthing"`
+    /// - `"fc-cache -f"`
+    pub after: Option<Vec<String>>,
+}
+
+impl Group {
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or("untitled")
+    }
 }
 
 pub async fn read_config(path: &Path) -> Result<Config> {
@@ -143,4 +202,21 @@ mod tests {
             serde_json::to_string(&global_schema).expect("Failed to serialize schema");
         fs::write("schema.json", global_schema).expect("Failed to write schema");
     }
+
+    #[test]
+    fn parse_batch_hooks() {
+        let batch: Batch = serde_yaml::from_str(
+            r#"
+            manager: pacman
+            install:
+              repo: [fontconfig]
+            after: ["fc-cache -f"]
+            "#,
+        )
+        .expect("Failed to parse batch");
+
+        assert!(matches!(batch.config, PackageManagerConfig::Pacman(_)));
+        assert_eq!(batch.hooks.after, Some(vec!["fc-cache -f".to_string()]));
+        assert_eq!(batch.hooks.before, None);
+    }
 }
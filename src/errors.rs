@@ -33,6 +33,250 @@ pub struct UnsupportedPlatform {
     pub manager: &'static str,
 }
 
+#[derive(Error, Debug, Diagnostic)]
+#[error("pacman repository {name:?} is declared differently by multiple batches")]
+#[diagnostic(
+    code(package::pacman::conflicting_repository),
+    help(
+        "Batches installing into the same system share their repositories, declare it the same way everywhere."
+    )
+)]
+pub struct ConflictingPacmanRepository {
+    pub name: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("unable to read pacman config")]
+#[diagnostic(
+    code(package::pacman::config_read_fail),
+    help("Ensure that the file {path:?} exists.")
+)]
+pub struct UnableToReadPacmanConf {
+    pub path: PathBuf,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("unable to read state file: {reason}")]
+#[diagnostic(
+    code(state::read_fail),
+    help("The file {path:?} only records what was installed, move it away to start over.")
+)]
+pub struct UnableToReadState {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("unable to read lockfile: {reason}")]
+#[diagnostic(
+    code(lock::read_fail),
+    help("Run `chezpilot lock` to create {path:?}.")
+)]
+pub struct UnableToReadLockfile {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("profile {profile:?} is not locked")]
+#[diagnostic(
+    code(lock::profile_not_locked),
+    help(
+        "Run `chezpilot lock --profile {profile}` on this machine, or pick a locked profile with `--profile`."
+    )
+)]
+pub struct ProfileNotLocked {
+    pub profile: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum LockDeviation {
+    #[error("{manager} package {package:?} of {group} is at {installed}, but locked at {locked}")]
+    #[diagnostic(code(lock::version_mismatch))]
+    VersionMismatch {
+        group: String,
+        manager: String,
+        package: String,
+        installed: String,
+        locked: String,
+    },
+
+    #[error("{manager} package {package:?} of {group} is not locked")]
+    #[diagnostic(code(lock::unlocked))]
+    Unlocked {
+        group: String,
+        manager: String,
+        package: String,
+    },
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("{} packages deviate from the lockfile", .deviations.len())]
+#[diagnostic(
+    code(lock::deviations),
+    help("Run `chezpilot lock --profile {profile}` to lock the installed versions instead.")
+)]
+pub struct LockDeviations {
+    pub profile: String,
+    #[related]
+    pub deviations: Vec<LockDeviation>,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("package {name:?} was not found in {origin}")]
+#[diagnostic(code(package::not_found))]
+pub struct PackageNotFound {
+    pub name: String,
+    pub origin: &'static str,
+    #[help]
+    pub suggestion: Option<String>,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error(
+    "package {name:?} is declared under `{declared}`, but is only available from `{available}`"
+)]
+#[diagnostic(
+    code(package::misplaced),
+    help("Move it to `{available}` or set `auto_route: true` to install it from there.")
+)]
+pub struct MisplacedPackage {
+    pub name: String,
+    pub declared: &'static str,
+    pub available: &'static str,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("script {name:?} sets {found}")]
+#[diagnostic(
+    code(package::script::invalid_source),
+    help("Set either `install` to a command or `url` to an installer script, but not both.")
+)]
+pub struct InvalidScriptSource {
+    pub name: String,
+    pub found: &'static str,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum PackageProblem {
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    NotFound(#[from] PackageNotFound),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Misplaced(#[from] MisplacedPackage),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    InvalidScript(#[from] InvalidScriptSource),
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("{} declared packages are invalid", .problems.len())]
+#[diagnostic(
+    code(package::invalid),
+    help("Fix the package declarations in your configuration file.")
+)]
+pub struct InvalidPackages {
+    #[related]
+    pub problems: Vec<PackageProblem>,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("invalid package file: {reason}")]
+#[diagnostic(
+    code(package::pacman::invalid_file),
+    help("Ensure that {path:?} is a pacman package archive, e.g. a `.pkg.tar.zst` file.")
+)]
+pub struct InvalidPackageFile {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("verification of {artifact} failed: {reason}")]
+#[diagnostic(
+    code(verify::failed),
+    help(
+        "Nothing was installed or run. If {artifact} changed legitimately, update its `sha256` or `signature` after checking where the change came from."
+    )
+)]
+pub struct VerificationFailed {
+    pub artifact: String,
+    pub reason: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("{manager} does not support rolling back")]
+#[diagnostic(
+    code(rollback::unsupported),
+    help("Remove or downgrade the packages installed with {manager} by hand.")
+)]
+pub struct RollbackUnsupported {
+    pub manager: &'static str,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("{name} {version} is not in the package cache")]
+#[diagnostic(
+    code(package::pacman::not_cached),
+    help(
+        "Package files are only kept until the cache is cleaned, e.g. by `paccache` or `pacman -Sc`. Downgrade {name} by hand with a package file from elsewhere."
+    )
+)]
+pub struct CachedPackageNotFound {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("no release of {repo} matches {selector}")]
+#[diagnostic(
+    code(package::release::not_found),
+    help("Check `repo`, `tag` and `version`. Drafts and pre-releases never meet a `version`.")
+)]
+pub struct ReleaseNotFound {
+    pub repo: String,
+    pub selector: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("no asset of {repo} {tag} matches {pattern:?}")]
+#[diagnostic(code(package::release::asset_not_found))]
+pub struct ReleaseAssetNotFound {
+    pub repo: String,
+    pub tag: String,
+    pub pattern: String,
+    #[help]
+    pub available: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("{} assets of {repo} {tag} match {pattern:?}", .matched.len())]
+#[diagnostic(
+    code(package::release::ambiguous_asset),
+    help("Make `asset` match only one of: {}", .matched.join(", "))
+)]
+pub struct AmbiguousReleaseAsset {
+    pub repo: String,
+    pub tag: String,
+    pub pattern: String,
+    pub matched: Vec<String>,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("unable to extract {binary:?} from {asset}: {reason}")]
+#[diagnostic(
+    code(package::release::invalid_archive),
+    help("Set `binary` to the name of the executable inside {asset}.")
+)]
+pub struct InvalidReleaseArchive {
+    pub asset: String,
+    pub binary: String,
+    pub reason: String,
+}
+
 #[derive(Error, Debug, Diagnostic)]
 #[error("no AUR helper found")]
 #[diagnostic(
@@ -43,6 +287,14 @@ pub struct UnsupportedPlatform {
 )]
 pub struct AurHelperNotDetected;
 
+#[derive(Error, Debug, Diagnostic)]
+#[error("makepkg can't be run as root")]
+#[diagnostic(
+    code(package::aur::makepkg_as_root),
+    help("Install an AUR helper or run chezpilot as a regular user to build AUR packages.")
+)]
+pub struct MakepkgAsRoot;
+
 #[derive(Debug, Display)]
 pub enum AurHelperPinReason {
     #[strum(serialize = "a config override")]
@@ -63,3 +315,155 @@ pub struct RequestedAurHelperNotFound {
     pub helper: String,
     pub reason: AurHelperPinReason,
 }
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("group {group:?} needs {dependency:?}, which does not exist")]
+#[diagnostic(
+    code(group::unknown_dependency),
+    help("Ensure that a group named {dependency:?} is declared in your configuration file.")
+)]
+pub struct UnknownGroupDependency {
+    pub group: String,
+    pub dependency: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("group {group:?} needs {dependency:?}, which does not match this system")]
+#[diagnostic(
+    code(group::unmatched_dependency),
+    help(
+        "Conditions of {dependency:?} exclude this system. Update the conditions of either group so that they match together."
+    )
+)]
+pub struct UnmatchedGroupDependency {
+    pub group: String,
+    pub dependency: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("group name {name:?} is used more than once")]
+#[diagnostic(
+    code(group::duplicate_name),
+    help(
+        "Group names referenced in `needs` have to be unique among groups that match at the same time."
+    )
+)]
+pub struct DuplicateGroupName {
+    pub name: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("dependency cycle between groups: {}", cycle.join(" → "))]
+#[diagnostic(
+    code(group::dependency_cycle),
+    help("Remove one of the `needs` entries to break the cycle.")
+)]
+pub struct GroupDependencyCycle {
+    pub cycle: Vec<String>,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("hook `{command}` failed: {reason}")]
+#[diagnostic(
+    code(hook::failed),
+    help("Check the hook output above and make sure the command succeeds when run manually.")
+)]
+pub struct HookFailed {
+    pub command: String,
+    pub reason: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("{failed} group(s) failed to install")]
+#[diagnostic(
+    code(apply::failed),
+    help("See the summary above for the error codes of every failed step.")
+)]
+pub struct ApplyFailed {
+    pub failed: usize,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("root privileges are required, but no privilege escalation tool was found")]
+#[diagnostic(
+    code(privilege::tool_not_detected),
+    help(
+        "Install sudo, doas, run0 or pkexec, run as root, or set the ELEVATION_TOOL environment variable to point to your preferred one."
+    )
+)]
+pub struct ElevationToolNotDetected;
+
+#[derive(Debug, Display)]
+pub enum ElevationPinReason {
+    #[strum(serialize = "the --elevation-tool flag")]
+    Flag,
+    #[strum(serialize = "an env override (ELEVATION_TOOL)")]
+    Env,
+    #[strum(serialize = "a config override")]
+    Config,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("{tool}: privilege escalation tool not found")]
+#[diagnostic(
+    code(privilege::requested_tool_not_found),
+    help(
+        "This tool was specified by {reason}, but could not be found on your system. Supported tools are sudo, doas, run0 and pkexec."
+    )
+)]
+pub struct RequestedElevationToolNotFound {
+    pub tool: String,
+    pub reason: ElevationPinReason,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("{tool}: failed to acquire root privileges")]
+#[diagnostic(
+    code(privilege::authentication_failed),
+    help(
+        "Root privileges are needed to install missing packages. Make sure your user is allowed to use {tool}."
+    )
+)]
+pub struct PrivilegesNotAcquired {
+    pub tool: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("{tool}: a password is required in non-interactive mode")]
+#[diagnostic(
+    code(privilege::password_required),
+    help(
+        "Allow your user to run commands without a password (e.g. NOPASSWD for sudo or nopass for doas), run as root, or run without --yes."
+    )
+)]
+pub struct PasswordRequired {
+    pub tool: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("installation cancelled")]
+#[diagnostic(
+    code(apply::cancelled),
+    help("Nothing was installed. Pass --yes to skip the confirmation.")
+)]
+pub struct Cancelled;
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("installing {manager} packages shared with other groups failed")]
+#[diagnostic(
+    code(apply::combined_failed),
+    help(
+        "The packages were installed in one transaction with other groups, its error is printed above."
+    )
+)]
+pub struct CombinedInstallFailed {
+    pub manager: String,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+#[error("interrupted")]
+#[diagnostic(
+    code(apply::interrupted),
+    help("The run was cancelled with Ctrl-C. Some groups may have been installed only partially.")
+)]
+pub struct Interrupted;
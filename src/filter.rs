@@ -6,7 +6,7 @@ use sysinfo::{RefreshKind, System};
 
 use crate::{
     GlobalArgs,
-    config::{Condition, OsName, OsType},
+    config::{Condition, Config, Group, OsName, OsType},
 };
 
 pub struct SystemInfo {
@@ -14,6 +14,9 @@ pub struct SystemInfo {
     pub distro: String,
     pub version: Option<semver::Version>,
     pub distro_like: Vec<String>,
+
+    /// CPU architecture, e.g. `x86_64` or `aarch64`.
+    pub arch: &'static str,
 }
 
 pub fn get_system_info() -> Result<SystemInfo> {
@@ -29,9 +32,30 @@ pub fn get_system_info() -> Result<SystemInfo> {
         distro,
         version,
         distro_like,
+        arch: std::env::consts::ARCH,
     })
 }
 
+/// Groups of `config` whose conditions all match this system.
+pub fn matching_groups(config: &Config, global_args: &GlobalArgs) -> Result<Vec<Group>> {
+    let system = get_system_info()?;
+
+    let groups = config
+        .groups
+        .iter()
+        .filter(|group| {
+            group
+                .conditions
+                .iter()
+                .filter_map(|condition_name| config.conditions.get(condition_name))
+                .all(|c| check_condition(&system, c, global_args))
+        })
+        .cloned()
+        .collect();
+
+    Ok(groups)
+}
+
 pub fn check_condition(
     system: &SystemInfo,
     condition: &Condition,
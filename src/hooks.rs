@@ -0,0 +1,55 @@
+use std::process::Stdio;
+
+use miette::Result;
+use owo_colors::OwoColorize;
+use tokio::process::Command;
+use tracing::{Instrument, info, info_span};
+
+use crate::errors::HookFailed;
+
+#[cfg(not(windows))]
+const SHELL: (&str, &str) = ("sh", "-c");
+#[cfg(windows)]
+const SHELL: (&str, &str) = ("cmd", "/C");
+
+/// Runs hook commands one by one, stopping at the first failure.
+pub async fn run_hooks(commands: Option<&Vec<String>>) -> Result<()> {
+    for command in commands.into_iter().flatten() {
+        info!("Running {}", command.blue().bold());
+        run_hook(command)
+            .instrument(info_span!("dotget::nested"))
+            .await?;
+    }
+    Ok(())
+}
+
+async fn run_hook(command: &str) -> Result<()> {
+    let (shell, flag) = SHELL;
+    let output = Command::new(shell)
+        .arg(flag)
+        .arg(command)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| HookFailed {
+            command: command.to_string(),
+            reason: e.to_string(),
+        })?;
+
+    for line in String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .chain(String::from_utf8_lossy(&output.stderr).lines())
+    {
+        info!("{}", line.dimmed());
+    }
+
+    if !output.status.success() {
+        let error = HookFailed {
+            command: command.to_string(),
+            reason: format!("exited with {}", output.status),
+        };
+        return Err(error.into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,260 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use miette::{IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    GlobalArgs,
+    config::Group,
+    errors::{LockDeviation, LockDeviations, UnableToReadLockfile},
+    package_managers::{PackageManagerConfig, PackageManagers},
+};
+
+/// Installed versions of a group's packages, keyed by package manager and then by package name.
+pub type LockedGroup = BTreeMap<String, BTreeMap<String, String>>;
+
+/// Locked groups of a machine, keyed by group name.
+pub type LockedProfile = BTreeMap<String, LockedGroup>;
+
+/// Versions of declared packages, written by `chezpilot lock`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Lockfile {
+    /// Locked groups keyed by machine profile, so that one repository can lock multiple hosts.
+    pub profiles: BTreeMap<String, LockedProfile>,
+}
+
+impl Lockfile {
+    /// Path of the lockfile next to the configuration file, e.g. `dotget.lock`.
+    pub fn path(config: &Path) -> PathBuf {
+        config.with_extension("lock")
+    }
+
+    pub async fn read(path: &Path) -> Result<Self> {
+        let invalid = |reason: String| UnableToReadLockfile {
+            path: path.to_path_buf(),
+            reason,
+        };
+
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|error| invalid(error.to_string()))?;
+        let lockfile =
+            serde_yaml::from_str(&content).map_err(|error| invalid(error.to_string()))?;
+        Ok(lockfile)
+    }
+
+    pub async fn write(&self, path: &Path) -> Result<()> {
+        let content = serde_yaml::to_string(self).into_diagnostic()?;
+        let content = format!("# Generated by `chezpilot lock`, do not edit by hand.\n{content}");
+        tokio::fs::write(path, content).await.into_diagnostic()
+    }
+}
+
+/// Machine profile locks are keyed by, `--profile` or the hostname.
+pub fn profile(global_args: &GlobalArgs) -> Result<String> {
+    if let Some(profile) = &global_args.profile {
+        return Ok(profile.clone());
+    }
+
+    match sysinfo::System::host_name() {
+        Some(hostname) => Ok(hostname),
+        None => miette::bail!("Unable to detect the hostname, pass a profile with `--profile`"),
+    }
+}
+
+/// Installed versions of packages declared by `groups`.
+///
+/// Packages without a known version, e.g. because they aren't installed yet,
+/// are left out and returned separately.
+pub async fn resolve(
+    managers: &PackageManagers,
+    groups: &[Group],
+) -> Result<(LockedProfile, Vec<String>)> {
+    let mut profile = LockedProfile::new();
+    let mut unresolved = Vec::new();
+
+    for group in groups {
+        profile.entry(group.display_name().to_string()).or_default();
+
+        for batch in &group.packages {
+            let versions = managers.installed_versions(&batch.config).await?;
+            let manager = batch.config.to_string();
+            unresolved.extend(insert(
+                &mut profile,
+                group.display_name(),
+                &manager,
+                versions,
+            ));
+        }
+    }
+
+    Ok((profile, unresolved))
+}
+
+/// Versions `installed` packages are at once the `missing` packages of each group are installed.
+///
+/// Missing packages whose version is only known once they are installed are left out,
+/// so that they are checked after installing instead.
+pub async fn planned<'a>(
+    managers: &PackageManagers,
+    mut installed: LockedProfile,
+    missing: impl IntoIterator<Item = (&'a Group, &'a PackageManagerConfig)>,
+) -> Result<LockedProfile> {
+    for (group, config) in missing {
+        let versions = managers.candidate_versions(config).await?;
+        overlay(
+            &mut installed,
+            group.display_name(),
+            &config.to_string(),
+            versions,
+        );
+    }
+
+    Ok(installed)
+}
+
+/// Replaces versions of `manager` packages of `group` in `profile` by the `versions` they are installed at.
+///
+/// Packages without a version are removed, as their installed version is about to change.
+pub fn overlay(
+    profile: &mut LockedProfile,
+    group: &str,
+    manager: &str,
+    versions: Vec<(String, Option<String>)>,
+) {
+    let packages = profile
+        .entry(group.to_string())
+        .or_default()
+        .entry(manager.to_string())
+        .or_default();
+
+    for (name, version) in versions {
+        match version {
+            Some(version) => packages.insert(name, version),
+            None => packages.remove(&name),
+        };
+    }
+}
+
+/// Records `versions` of `manager` packages of `group` in `profile`.
+///
+/// Returns the names of packages without a version, which are left out.
+pub fn insert(
+    profile: &mut LockedProfile,
+    group: &str,
+    manager: &str,
+    versions: Vec<(String, Option<String>)>,
+) -> Vec<String> {
+    let packages = profile
+        .entry(group.to_string())
+        .or_default()
+        .entry(manager.to_string())
+        .or_default();
+
+    let mut unresolved = Vec::new();
+    for (name, version) in versions {
+        match version {
+            Some(version) => {
+                packages.insert(name, version);
+            }
+            None => unresolved.push(name),
+        }
+    }
+    unresolved
+}
+
+/// Checks `installed` versions against the `locked` ones of `profile`.
+pub fn check(
+    profile: &str,
+    locked: &LockedProfile,
+    installed: &LockedProfile,
+) -> Result<(), LockDeviations> {
+    let deviations = deviations(locked, installed);
+    if deviations.is_empty() {
+        return Ok(());
+    }
+    Err(LockDeviations {
+        profile: profile.to_string(),
+        deviations,
+    })
+}
+
+/// Differences between `installed` versions and `locked` ones.
+///
+/// Packages that are only locked are fine, as they are either installed later or no longer declared.
+pub fn deviations(locked: &LockedProfile, installed: &LockedProfile) -> Vec<LockDeviation> {
+    let mut deviations = Vec::new();
+
+    for (group, managers) in installed {
+        for (manager, packages) in managers {
+            for (package, version) in packages {
+                let locked = locked
+                    .get(group)
+                    .and_then(|managers| managers.get(manager))
+                    .and_then(|packages| packages.get(package));
+
+                match locked {
+                    Some(locked) if locked == version => {}
+                    Some(locked) => deviations.push(LockDeviation::VersionMismatch {
+                        group: group.clone(),
+                        manager: manager.clone(),
+                        package: package.clone(),
+                        installed: version.clone(),
+                        locked: locked.clone(),
+                    }),
+                    None => deviations.push(LockDeviation::Unlocked {
+                        group: group.clone(),
+                        manager: manager.clone(),
+                        package: package.clone(),
+                    }),
+                }
+            }
+        }
+    }
+
+    deviations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(entries: &[(&str, &str)]) -> LockedProfile {
+        let packages = entries
+            .iter()
+            .map(|(name, version)| (name.to_string(), version.to_string()))
+            .collect();
+        let group = LockedGroup::from([("pacman".to_string(), packages)]);
+        LockedProfile::from([("base".to_string(), group)])
+    }
+
+    #[test]
+    fn finds_deviations_from_lock() {
+        let locked = profile(&[("git", "2.47.0-1"), ("zsh", "5.9-5"), ("vim", "9.1-1")]);
+        let installed = profile(&[("git", "2.47.0-1"), ("zsh", "5.9-6"), ("tmux", "3.5-1")]);
+
+        let deviations = deviations(&locked, &installed);
+
+        let [
+            LockDeviation::Unlocked {
+                package: unlocked, ..
+            },
+            LockDeviation::VersionMismatch {
+                package: changed,
+                installed,
+                locked,
+                ..
+            },
+        ] = &deviations[..]
+        else {
+            panic!("Expected an unlocked and a changed package, got {deviations:?}");
+        };
+        assert_eq!(unlocked, "tmux");
+        assert_eq!(changed, "zsh");
+        assert_eq!(installed, "5.9-6");
+        assert_eq!(locked, "5.9-5");
+    }
+}
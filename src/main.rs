@@ -3,21 +3,32 @@ mod config;
 mod errors;
 mod filter;
 mod formatter;
+mod hooks;
+mod lockfile;
+mod ordering;
 mod package_managers;
+mod privilege;
 mod report_handler;
+mod runner;
+mod state;
+mod summary;
+mod transactions;
 mod utils;
+mod verify;
 
 use std::{ffi::OsStr, path::PathBuf, process};
 
 use clap::{Parser, Subcommand};
 use miette::Result;
 use tracing::level_filters::LevelFilter;
+use tracing_indicatif::{IndicatifLayer, filter::IndicatifFilter};
 use tracing_subscriber::{Layer, fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
 use crate::{
-    commands::{apply, lint},
-    package_managers::PackageManagers,
+    commands::{apply, history, lint, lock, rollback, status},
+    privilege::ElevationTool,
     report_handler::ErrorReportHandler,
+    utils::get_spinner_style,
 };
 
 /// DotGet
@@ -40,6 +51,18 @@ pub struct GlobalArgs {
 
     #[arg(short = 'l', long, global = true)]
     labels: Vec<String>,
+
+    /// Tool used to run commands that need root (sudo, doas, run0 or pkexec)
+    #[arg(long, global = true)]
+    elevation_tool: Option<ElevationTool>,
+
+    /// Never prompt, answer every confirmation with yes and fail if a password is needed
+    #[arg(short = 'y', long, visible_alias = "non-interactive", global = true)]
+    yes: bool,
+
+    /// Machine profile of the lockfile, defaults to the hostname
+    #[arg(long, global = true)]
+    profile: Option<String>,
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -51,29 +74,49 @@ enum Commands {
     },
     /// Find potential issues with the configuration file
     Lint,
+    /// Show which declared packages are missing on this system
+    Status,
+    /// Lock installed versions of declared packages for this machine's profile
+    Lock,
+    /// Undo the last apply, removing newly installed packages and downgrading upgraded ones.
+    /// Installer scripts can't be undone and are only listed
+    Rollback {
+        #[command(flatten)]
+        args: rollback::RollbackArgs,
+    },
+    /// Show packages installed by previous runs and which group declared them
+    History {
+        #[command(flatten)]
+        args: history::HistoryArgs,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     miette::set_hook(Box::new(|_| Box::new(ErrorReportHandler::new())))?;
 
+    // Progress bars are only shown for spans that opt in with `indicatif.pb_show`
+    let indicatif_layer = IndicatifLayer::new().with_progress_style(get_spinner_style());
+
     tracing_subscriber::registry()
         .with(
             fmt::layer()
-                // .with_writer(indicatif_layer.get_stderr_writer())
+                .with_writer(indicatif_layer.get_stdout_writer())
                 .event_format(formatter::EventFormatter)
                 .with_filter(LevelFilter::INFO),
         )
-        // .with(indicatif_layer)
+        .with(indicatif_layer.with_filter(IndicatifFilter::new(false)))
         .init();
 
-    let managers = PackageManagers::new()?;
-
     let cli = Cli::parse();
 
     let result = match cli.command {
-        Commands::Apply { args } => apply::apply(managers, cli.args, args).await,
-        Commands::Lint => lint::lint(managers, cli.args).await,
+        Commands::Apply { args } => apply::apply(cli.args, args).await,
+        Commands::Lint => lint::lint(cli.args).await,
+        Commands::Status => status::status(cli.args).await,
+        Commands::Lock => lock::lock(cli.args).await,
+        Commands::Rollback { args } => rollback::rollback(cli.args, args).await,
+        Commands::History { args } => history::history(args).await,
     };
 
     if let Err(e) = result {
@@ -0,0 +1,270 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use miette::Result;
+
+use crate::{
+    config::{Condition, Group, OsName, OsType},
+    errors::{
+        DuplicateGroupName, GroupDependencyCycle, UnknownGroupDependency, UnmatchedGroupDependency,
+    },
+};
+
+/// Sorts matching groups so that every group comes after the groups it `needs`.
+///
+/// Groups that do not depend on each other keep their order from the configuration file.
+pub fn sort_groups(all: &[Group], matching: Vec<Group>) -> Result<Vec<Group>> {
+    check_duplicates(&matching)?;
+    order(all, matching)
+}
+
+/// Checks the ordering of all groups without knowing which of them match.
+///
+/// Needed groups sharing a name are rejected unless their `conditions` can never match at once,
+/// as the system `apply` runs on could match both of them.
+pub fn lint_groups(all: &[Group], conditions: &HashMap<String, Condition>) -> Result<()> {
+    let needed: HashSet<&str> = all
+        .iter()
+        .flat_map(|group| group.needs.iter().flatten())
+        .map(String::as_str)
+        .collect();
+
+    for (index, group) in all.iter().enumerate() {
+        let Some(name) = group.name.as_deref().filter(|name| needed.contains(name)) else {
+            continue;
+        };
+        let overlapping = all[..index].iter().any(|other| {
+            other.name.as_deref() == Some(name) && !exclusive(group, other, conditions)
+        });
+        if overlapping {
+            return Err(DuplicateGroupName {
+                name: name.to_string(),
+            }
+            .into());
+        }
+    }
+
+    order(all, all.to_vec()).map(|_| ())
+}
+
+/// Whether `a` and `b` can never match on the same system, because they require different operating systems.
+///
+/// Conditions with a label are left out, as a passed label matches regardless of the system.
+fn exclusive(a: &Group, b: &Group, conditions: &HashMap<String, Condition>) -> bool {
+    let os_names = |group: &Group| -> Vec<Vec<OsName>> {
+        group
+            .conditions
+            .iter()
+            .filter_map(|name| conditions.get(name))
+            .filter(|condition| condition.label.is_none())
+            .filter_map(|condition| condition.os.as_ref())
+            .map(|os| os.iter().map(OsType::name).collect())
+            .collect()
+    };
+
+    let (a, b) = (os_names(a), os_names(b));
+    a.iter()
+        .any(|a| b.iter().any(|b| !a.iter().any(|name| b.contains(name))))
+}
+
+/// Rejects names shared by multiple `matching` groups if another group `needs` them,
+/// as it would be ambiguous which of them it depends on.
+fn check_duplicates(matching: &[Group]) -> Result<()> {
+    let needed: HashSet<&str> = matching
+        .iter()
+        .flat_map(|group| group.needs.iter().flatten())
+        .map(String::as_str)
+        .collect();
+
+    let mut seen = HashSet::new();
+    for name in matching.iter().filter_map(|group| group.name.as_deref()) {
+        if !seen.insert(name) && needed.contains(name) {
+            return Err(DuplicateGroupName {
+                name: name.to_string(),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+fn order(all: &[Group], matching: Vec<Group>) -> Result<Vec<Group>> {
+    // Unreferenced duplicates are fine, dependencies resolve to the first declared group
+    let mut indices = HashMap::new();
+    for (index, group) in matching.iter().enumerate() {
+        if let Some(name) = &group.name {
+            indices.entry(name.as_str()).or_insert(index);
+        }
+    }
+
+    // `dependencies[i]` holds indices of groups needed by group `i`
+    let mut dependencies = vec![Vec::new(); matching.len()];
+    for (index, group) in matching.iter().enumerate() {
+        for need in group.needs.iter().flatten() {
+            if let Some(&dependency) = indices.get(need.as_str()) {
+                dependencies[index].push(dependency);
+                continue;
+            }
+
+            let group = group.display_name().to_string();
+            let dependency = need.clone();
+            if all.iter().any(|g| g.name.as_ref() == Some(need)) {
+                return Err(UnmatchedGroupDependency { group, dependency }.into());
+            }
+            return Err(UnknownGroupDependency { group, dependency }.into());
+        }
+    }
+
+    let mut dependents = vec![Vec::new(); matching.len()];
+    for (index, needs) in dependencies.iter().enumerate() {
+        for &dependency in needs {
+            dependents[dependency].push(index);
+        }
+    }
+
+    // Kahn's algorithm, always picking the earliest declared group that is ready
+    let mut remaining: Vec<usize> = dependencies.iter().map(Vec::len).collect();
+    let mut ready: BTreeSet<usize> = (0..matching.len())
+        .filter(|&index| remaining[index] == 0)
+        .collect();
+    let mut order = Vec::with_capacity(matching.len());
+
+    while let Some(index) = ready.pop_first() {
+        order.push(index);
+        for &dependent in &dependents[index] {
+            remaining[dependent] -= 1;
+            if remaining[dependent] == 0 {
+                ready.insert(dependent);
+            }
+        }
+    }
+
+    if order.len() < matching.len() {
+        let cycle = find_cycle(&dependencies, &remaining)
+            .into_iter()
+            .map(|index| matching[index].display_name().to_string())
+            .collect();
+        return Err(GroupDependencyCycle { cycle }.into());
+    }
+
+    let mut groups: Vec<Option<Group>> = matching.into_iter().map(Some).collect();
+    Ok(order
+        .into_iter()
+        .filter_map(|index| groups[index].take())
+        .collect())
+}
+
+/// Walks unresolved dependencies until a group repeats.
+///
+/// Every group left with unresolved dependencies after sorting
+/// needs at least one other such group, so the walk always ends in a cycle.
+fn find_cycle(dependencies: &[Vec<usize>], remaining: &[usize]) -> Vec<usize> {
+    let unresolved = |index: &usize| remaining[*index] > 0;
+
+    let mut path = Vec::new();
+    let mut current = (0..remaining.len()).find(unresolved);
+
+    while let Some(index) = current {
+        if let Some(start) = path.iter().position(|&visited| visited == index) {
+            let mut cycle = path.split_off(start);
+            cycle.push(index);
+            return cycle;
+        }
+        path.push(index);
+        current = dependencies[index].iter().copied().find(unresolved);
+    }
+
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groups(yaml: &str) -> Vec<Group> {
+        serde_yaml::from_str(yaml).expect("Failed to parse groups")
+    }
+
+    fn names(groups: &[Group]) -> Vec<&str> {
+        groups.iter().map(Group::display_name).collect()
+    }
+
+    #[test]
+    fn dependencies_come_first() {
+        let all = groups(
+            r#"
+            - { name: rust-tools, conditions: [], needs: [base-devel], packages: [] }
+            - { name: fonts, conditions: [], packages: [] }
+            - { name: base-devel, conditions: [], packages: [] }
+            "#,
+        );
+
+        let sorted = sort_groups(&all, all.clone()).unwrap();
+        assert_eq!(names(&sorted), ["fonts", "base-devel", "rust-tools"]);
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        let all = groups(
+            r#"
+            - { name: a, conditions: [], needs: [b], packages: [] }
+            - { name: b, conditions: [], needs: [a], packages: [] }
+            "#,
+        );
+
+        let error = sort_groups(&all, all.clone()).unwrap_err();
+        let cycle = error.downcast_ref::<GroupDependencyCycle>().unwrap();
+        assert_eq!(cycle.cycle, ["a", "b", "a"]);
+    }
+
+    #[test]
+    fn unmatched_dependencies_are_reported() {
+        let all = groups(
+            r#"
+            - { name: a, conditions: [], needs: [b], packages: [] }
+            - { name: b, conditions: [], packages: [] }
+            "#,
+        );
+
+        let error = sort_groups(&all, all[..1].to_vec()).unwrap_err();
+        assert!(error.is::<UnmatchedGroupDependency>());
+
+        let error = sort_groups(&all[..1], all[..1].to_vec()).unwrap_err();
+        assert!(error.is::<UnknownGroupDependency>());
+    }
+
+    fn conditions(yaml: &str) -> HashMap<String, Condition> {
+        serde_yaml::from_str(yaml).expect("Failed to parse conditions")
+    }
+
+    #[test]
+    fn only_needed_duplicates_are_reported() {
+        let all = groups(
+            r#"
+            - { name: extras, conditions: [], packages: [] }
+            - { name: extras, conditions: [], packages: [] }
+            - { name: base, conditions: [linux], packages: [] }
+            - { name: base, conditions: [macos], packages: [] }
+            - { name: tools, conditions: [], needs: [base], packages: [] }
+            "#,
+        );
+        let conditions = conditions(
+            r#"
+            linux: { os: [{ kind: linux }] }
+            macos: { os: [{ kind: macos }] }
+            laptop: { hostname_pattern: "laptop-*" }
+            "#,
+        );
+
+        lint_groups(&all, &conditions).unwrap();
+        sort_groups(&all, vec![all[0].clone(), all[1].clone()]).unwrap();
+
+        let error = sort_groups(&all, all[2..].to_vec()).unwrap_err();
+        assert!(error.is::<DuplicateGroupName>());
+
+        // A Linux laptop matches both
+        let mut overlapping = all.clone();
+        overlapping[3].conditions = vec!["laptop".to_string()];
+        let error = lint_groups(&overlapping, &conditions).unwrap_err();
+        assert!(error.is::<DuplicateGroupName>());
+    }
+}
@@ -1,25 +1,128 @@
 pub mod pacman;
+pub mod release;
+pub mod script;
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use async_trait::async_trait;
-use miette::Result;
-use owo_colors::OwoColorize;
+use miette::{IntoDiagnostic, Result};
 use schemars::JsonSchema;
-use serde::Deserialize;
-use tracing::info;
+use serde::{Deserialize, Serialize};
 
-use crate::{config::OsName, errors::UnsupportedPlatform};
+use crate::{
+    GlobalArgs,
+    config::{Config, OsName},
+    errors::{PackageProblem, RollbackUnsupported, UnsupportedPlatform},
+    privilege::Privileges,
+    runner::{CommandRunner, Invocation, SystemRunner},
+    transactions::Change,
+    utils::cache_dir,
+    verify::Keys,
+};
+
+/// Settings shared by all package managers.
+#[derive(Debug, Clone)]
+pub struct Context {
+    pub privileges: Privileges,
+
+    /// If `false`, nothing may prompt the user and stdin is never attached.
+    pub interactive: bool,
+
+    pub runner: Arc<dyn CommandRunner>,
+
+    /// Directory of the configuration file, relative paths in it are resolved against it.
+    pub config_dir: PathBuf,
+
+    /// Keys declared in the configuration, used to verify signatures of downloads.
+    pub keys: Keys,
+}
+
+impl Context {
+    pub fn new(global_args: &GlobalArgs, config: &Config) -> Result<Self> {
+        let privileges = Privileges::detect(global_args.elevation_tool, config.elevation_tool)?;
+        let config_dir = match global_args.file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        Ok(Self {
+            privileges,
+            interactive: !global_args.yes,
+            runner: Arc::new(SystemRunner),
+            config_dir,
+            keys: Keys::new(config.keys.clone()),
+        })
+    }
+
+    /// Resolves a path from the configuration file against its directory, expanding a leading `~`.
+    pub fn resolve_path(&self, path: &Path) -> PathBuf {
+        if let Ok(relative) = path.strip_prefix("~")
+            && let Some(home) = std::env::var_os("HOME")
+        {
+            return PathBuf::from(home).join(relative);
+        }
+        self.config_dir.join(path)
+    }
+
+    /// Creates an invocation of `program` that may read from the terminal if running interactively.
+    pub fn invocation(&self, program: &str) -> Invocation {
+        Invocation::new(program).stdin(self.interactive)
+    }
+
+    /// Creates an invocation that runs `program` as root.
+    pub fn elevated(&self, program: &str) -> Result<Invocation> {
+        let invocation = self.privileges.invocation(program, self.interactive)?;
+        Ok(invocation.stdin(self.interactive))
+    }
+
+    /// Writes a file owned by root, creating missing parent directories.
+    pub async fn write_file(&self, path: &Path, content: &str) -> Result<()> {
+        if self.privileges == Privileges::Root {
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await.into_diagnostic()?;
+            }
+            return tokio::fs::write(path, content).await.into_diagnostic();
+        }
+
+        // Written as the current user first, then moved into place with the right owner
+        let Some(dir) = cache_dir() else {
+            miette::bail!("Unable to find a cache directory to stage {path:?} in");
+        };
+        tokio::fs::create_dir_all(&dir).await.into_diagnostic()?;
+        let staged = dir.join(format!("staged-{}", std::process::id()));
+        tokio::fs::write(&staged, content).await.into_diagnostic()?;
+
+        let invocation = self
+            .elevated("install")?
+            .args(["-D", "-m", "644"])
+            .arg(staged.display().to_string())
+            .arg(path.display().to_string());
+        let status = self.runner.status(&invocation).await.into_diagnostic();
+        tokio::fs::remove_file(&staged).await.ok();
+
+        if !status?.success() {
+            miette::bail!("Failed to write {path:?}");
+        }
+        Ok(())
+    }
+}
 
 #[async_trait]
 pub trait PackageManager {
     const NAME: &'static str;
     const SUPPORTED_OS: &'static [OsName];
 
-    type Options: for<'de> Deserialize<'de> + JsonSchema + Send + Sync + Clone;
+    type Options: for<'de> Deserialize<'de> + Serialize + JsonSchema + Send + Sync + Clone;
     type Package: for<'de> Deserialize<'de> + JsonSchema + Send + Sync + Clone;
 
-    async fn get_installed(&self) -> Result<HashMap<String, Self::Package>> {
+    async fn get_installed(
+        &self,
+        _options: &Self::Options,
+    ) -> Result<HashMap<String, Self::Package>> {
         let error = UnsupportedPlatform {
             manager: Self::NAME,
         };
@@ -37,17 +140,114 @@ pub trait PackageManager {
         Err(error.into())
     }
 
-    async fn install_missing(&self, config: Self::Options) -> Result<()> {
-        let installed = self.get_installed().await?;
+    /// Names of packages declared in `options`.
+    fn packages(&self, _options: &Self::Options) -> Vec<String> {
+        Vec::new()
+    }
 
-        let (missing, count) = self.filter_missing(installed, &config)?;
-        if count == 0 {
-            return Ok(());
-        }
-        info!("Found {} missing packages", count.blue().bold());
+    /// Version of an installed package, if the package manager reports one.
+    fn version(&self, _package: &Self::Package) -> Option<String> {
+        None
+    }
+
+    /// Packages declared in `options` with their installed versions, keyed by the name
+    /// the package manager knows them by, e.g. the package providing a declared virtual package.
+    ///
+    /// Packages that aren't installed, or whose version is unknown, have no version.
+    async fn installed_versions(
+        &self,
+        options: &Self::Options,
+    ) -> Result<Vec<(String, Option<String>)>> {
+        let installed = self.get_installed(options).await?;
+        let versions = self
+            .packages(options)
+            .into_iter()
+            .map(|name| {
+                let version = installed
+                    .get(&name)
+                    .and_then(|package| self.version(package));
+                (name, version)
+            })
+            .collect();
+        Ok(versions)
+    }
+
+    /// Packages installing `missing` would install, with the versions they would be installed at,
+    /// keyed like `installed_versions`.
+    ///
+    /// Packages whose version is only known once they are installed have no version.
+    async fn candidate_versions(
+        &self,
+        missing: &Self::Options,
+    ) -> Result<Vec<(String, Option<String>)>> {
+        let versions = self
+            .packages(missing)
+            .into_iter()
+            .map(|name| (name, None))
+            .collect();
+        Ok(versions)
+    }
+
+    /// Merges `other` into `combined`, so that both are installed in a single transaction.
+    ///
+    /// Returns `false` if they can't share a transaction, e.g. because of different arguments.
+    fn merge(&self, _combined: &mut Self::Options, _other: &Self::Options) -> bool {
+        false
+    }
+
+    /// Commands run to install `options`, shown to the user before installing.
+    fn commands(&self, _options: &Self::Options) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Whether installing `options` runs anything as root.
+    fn needs_root(&self, _options: &Self::Options) -> bool {
+        false
+    }
 
-        self.install(missing).await?;
+    /// Problems with declared packages, e.g. ones that don't exist in the package manager's sources.
+    async fn validate(&self, _options: &Self::Options) -> Result<Vec<PackageProblem>> {
+        Ok(Vec::new())
+    }
+
+    async fn find_missing(&self, config: &Self::Options) -> Result<(Self::Options, usize)> {
+        let installed = self.get_installed(config).await?;
+        self.filter_missing(installed, config)
+    }
 
+    /// Declared packages of `options` that are installed, but not the way they are declared,
+    /// e.g. as dependencies of other packages. They are fixed by `remark` rather than installed,
+    /// so they are neither missing nor recorded as installed by chezpilot.
+    async fn find_remarks(&self, _options: &Self::Options) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Commands run to re-mark `packages`, shown to the user before running them.
+    fn remark_commands(&self, _options: &Self::Options, _packages: &[String]) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Re-marks `packages` found by `find_remarks`.
+    async fn remark(&self, _options: &Self::Options, _packages: &[String]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Downloads and verifies what installing `options` would use, without installing anything.
+    ///
+    /// Run for every missing batch before anything is installed, so that a failed verification aborts the run.
+    async fn verify(&self, _options: &Self::Options) -> Result<()> {
+        Ok(())
+    }
+
+    /// Steps `prepare` would take for `options` of every missing batch, shown in the plan.
+    async fn preparation(&self, _options: &[&Self::Options]) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Sets up what installing `options` of every missing batch needs, e.g. third-party repositories.
+    ///
+    /// Run once before anything is installed, as batches of different groups may share the setup.
+    async fn prepare(&self, _options: &[&Self::Options]) -> Result<()> {
         Ok(())
     }
 
@@ -57,6 +257,30 @@ pub trait PackageManager {
         };
         Err(error.into())
     }
+
+    /// Versions of installed packages, keyed by name,
+    /// or `None` if the package manager can't tell what installing changed.
+    ///
+    /// Taken before and after installing to log what changed, so that `rollback` can revert it.
+    async fn snapshot(&self, _options: &Self::Options) -> Result<Option<HashMap<String, String>>> {
+        Ok(None)
+    }
+
+    /// Names of installed packages that installing `options` may have changed,
+    /// or `None` if every change between snapshots belongs to it.
+    ///
+    /// Changes to other packages, e.g. made by other lanes meanwhile, are not logged.
+    async fn affected(&self, _options: &Self::Options) -> Result<Option<HashSet<String>>> {
+        Ok(None)
+    }
+
+    /// Reverts `changes` made while installing `options`.
+    async fn rollback(&self, _options: &Self::Options, _changes: &[Change]) -> Result<()> {
+        let error = RollbackUnsupported {
+            manager: Self::NAME,
+        };
+        Err(error.into())
+    }
 }
 
 macro_rules! package_managers {
@@ -67,12 +291,21 @@ macro_rules! package_managers {
             $($name),*
         }
 
-        #[derive(serde::Deserialize, schemars::JsonSchema, strum::Display, Debug, Clone)]
+        #[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, strum::Display, Debug, Clone)]
         #[serde(tag = "manager", content = "install", rename_all = "lowercase")]
+        #[strum(serialize_all = "lowercase")]
         pub enum PackageManagerConfig {
             $($name(<$struct as PackageManager>::Options)),*
         }
 
+        impl PackageManagerConfig {
+            pub fn manager(&self) -> PackageManagerName {
+                match self {
+                    $(Self::$name(_) => PackageManagerName::$name),*
+                }
+            }
+        }
+
         paste::paste! {
             #[derive(Clone)]
             pub struct PackageManagers {
@@ -82,20 +315,191 @@ macro_rules! package_managers {
             }
 
             impl PackageManagers {
-                pub fn new() -> miette::Result<Self> {
+                pub fn new(context: Context) -> miette::Result<Self> {
                     let managers = Self {
                         $(
-                            [< $name: lower >]: std::sync::Arc::new($struct::new()?)
+                            [< $name: lower >]: std::sync::Arc::new($struct::new(context.clone())?)
                         ),*
                     };
                     Ok(managers)
                 }
 
 
-                pub async fn install_missing(&self, config: PackageManagerConfig) -> Result<()> {
+                pub async fn find_missing(
+                    &self,
+                    config: &PackageManagerConfig,
+                ) -> Result<(PackageManagerConfig, usize)> {
+                    match config {
+                        $(
+                            PackageManagerConfig::$name(options) => {
+                                let (missing, count) = <$struct as PackageManager>::find_missing(&self.[< $name:lower >], options).await?;
+                                Ok((PackageManagerConfig::$name(missing), count))
+                            }
+                        ),*
+                    }
+                }
+
+                pub fn packages(&self, config: &PackageManagerConfig) -> Vec<String> {
+                    match config {
+                        $(
+                            PackageManagerConfig::$name(options) => <$struct as PackageManager>::packages(&self.[< $name:lower >], options)
+                        ),*
+                    }
+                }
+
+                /// Declared packages of `config` along with their installed versions.
+                ///
+                /// Packages that aren't installed, or whose version is unknown, have no version.
+                pub async fn installed_versions(&self, config: &PackageManagerConfig) -> Result<Vec<(String, Option<String>)>> {
+                    match config {
+                        $(
+                            PackageManagerConfig::$name(options) => <$struct as PackageManager>::installed_versions(&self.[< $name:lower >], options).await
+                        ),*
+                    }
+                }
+
+                /// Packages installing `missing` would install, with the versions they would be installed at.
+                pub async fn candidate_versions(&self, missing: &PackageManagerConfig) -> Result<Vec<(String, Option<String>)>> {
+                    match missing {
+                        $(
+                            PackageManagerConfig::$name(options) => <$struct as PackageManager>::candidate_versions(&self.[< $name:lower >], options).await
+                        ),*
+                    }
+                }
+
+                pub fn commands(&self, config: &PackageManagerConfig) -> Vec<String> {
+                    match config {
+                        $(
+                            PackageManagerConfig::$name(options) => <$struct as PackageManager>::commands(&self.[< $name:lower >], options)
+                        ),*
+                    }
+                }
+
+                pub fn needs_root(&self, config: &PackageManagerConfig) -> bool {
+                    match config {
+                        $(
+                            PackageManagerConfig::$name(options) => <$struct as PackageManager>::needs_root(&self.[< $name:lower >], options)
+                        ),*
+                    }
+                }
+
+                pub fn merge(
+                    &self,
+                    combined: &mut PackageManagerConfig,
+                    other: &PackageManagerConfig,
+                ) -> bool {
+                    #[allow(unreachable_patterns)]
+                    match (combined, other) {
+                        $(
+                            (PackageManagerConfig::$name(combined), PackageManagerConfig::$name(other)) => <$struct as PackageManager>::merge(&self.[< $name:lower >], combined, other),
+                        )*
+                        _ => false,
+                    }
+                }
+
+                pub async fn validate(&self, config: &PackageManagerConfig) -> Result<Vec<PackageProblem>> {
+                    match config {
+                        $(
+                            PackageManagerConfig::$name(options) => <$struct as PackageManager>::validate(&self.[< $name:lower >], options).await
+                        ),*
+                    }
+                }
+
+                pub async fn find_remarks(&self, config: &PackageManagerConfig) -> Result<Vec<String>> {
+                    match config {
+                        $(
+                            PackageManagerConfig::$name(options) => <$struct as PackageManager>::find_remarks(&self.[< $name:lower >], options).await
+                        ),*
+                    }
+                }
+
+                pub fn remark_commands(&self, config: &PackageManagerConfig, packages: &[String]) -> Vec<String> {
+                    match config {
+                        $(
+                            PackageManagerConfig::$name(options) => <$struct as PackageManager>::remark_commands(&self.[< $name:lower >], options, packages)
+                        ),*
+                    }
+                }
+
+                pub async fn remark(&self, config: &PackageManagerConfig, packages: &[String]) -> Result<()> {
+                    match config {
+                        $(
+                            PackageManagerConfig::$name(options) => <$struct as PackageManager>::remark(&self.[< $name:lower >], options, packages).await
+                        ),*
+                    }
+                }
+
+                pub async fn verify(&self, config: &PackageManagerConfig) -> Result<()> {
+                    match config {
+                        $(
+                            PackageManagerConfig::$name(options) => <$struct as PackageManager>::verify(&self.[< $name:lower >], options).await
+                        ),*
+                    }
+                }
+
+                pub async fn preparation(&self, configs: &[&PackageManagerConfig]) -> Result<Vec<String>> {
+                    let mut steps = Vec::new();
+                    $(
+                        let options: Vec<_> = configs
+                            .iter()
+                            .filter_map(|config| match config {
+                                PackageManagerConfig::$name(options) => Some(options),
+                                #[allow(unreachable_patterns)]
+                                _ => None,
+                            })
+                            .collect();
+                        if !options.is_empty() {
+                            steps.extend(<$struct as PackageManager>::preparation(&self.[< $name:lower >], &options).await?);
+                        }
+                    )*
+                    Ok(steps)
+                }
+
+                pub async fn prepare(&self, configs: &[&PackageManagerConfig]) -> Result<()> {
+                    $(
+                        let options: Vec<_> = configs
+                            .iter()
+                            .filter_map(|config| match config {
+                                PackageManagerConfig::$name(options) => Some(options),
+                                #[allow(unreachable_patterns)]
+                                _ => None,
+                            })
+                            .collect();
+                        if !options.is_empty() {
+                            <$struct as PackageManager>::prepare(&self.[< $name:lower >], &options).await?;
+                        }
+                    )*
+                    Ok(())
+                }
+
+                pub async fn install(&self, config: PackageManagerConfig) -> Result<()> {
+                    match config {
+                        $(
+                            PackageManagerConfig::$name(options) => <$struct as PackageManager>::install(&self.[< $name:lower >], options).await
+                        ),*
+                    }
+                }
+
+                pub async fn snapshot(&self, config: &PackageManagerConfig) -> Result<Option<HashMap<String, String>>> {
+                    match config {
+                        $(
+                            PackageManagerConfig::$name(options) => <$struct as PackageManager>::snapshot(&self.[< $name:lower >], options).await
+                        ),*
+                    }
+                }
+
+                pub async fn affected(&self, config: &PackageManagerConfig) -> Result<Option<HashSet<String>>> {
+                    match config {
+                        $(
+                            PackageManagerConfig::$name(options) => <$struct as PackageManager>::affected(&self.[< $name:lower >], options).await
+                        ),*
+                    }
+                }
+
+                pub async fn rollback(&self, config: &PackageManagerConfig, changes: &[Change]) -> Result<()> {
                     match config {
                         $(
-                            PackageManagerConfig::$name(options) => <$struct as PackageManager>::install_missing(&self.[< $name:lower >], options).await
+                            PackageManagerConfig::$name(options) => <$struct as PackageManager>::rollback(&self.[< $name:lower >], options, changes).await
                         ),*
                     }
                 }
@@ -106,4 +510,6 @@ macro_rules! package_managers {
 
 package_managers!(
     Pacman => pacman::Pacman,
+    Release => release::Release,
+    Script => script::Script,
 );
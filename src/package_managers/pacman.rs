@@ -1,22 +1,149 @@
 #[cfg(target_os = "linux")]
+mod aur;
+#[cfg(target_os = "linux")]
+mod conf;
+#[cfg(target_os = "linux")]
+mod files;
+#[cfg(target_os = "linux")]
+mod makepkg;
+#[cfg(target_os = "linux")]
+mod repositories;
+#[cfg(target_os = "linux")]
+mod rollback;
+#[cfg(target_os = "linux")]
 mod utils;
 
 #[cfg(target_os = "linux")]
-use alpm::Alpm;
+use alpm::{Alpm, SigLevel};
 use async_trait::async_trait;
 use miette::{IntoDiagnostic, Result};
 use schemars::JsonSchema;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 
-use crate::{config::OsName, package_managers::PackageManager};
+use crate::{
+    config::OsName,
+    errors::{MisplacedPackage, PackageNotFound, PackageProblem},
+    package_managers::{Context, PackageManager},
+    privilege::{ElevationTool, Privileges},
+    runner::Invocation,
+    utils::suggest,
+};
 
-pub struct Pacman {}
+#[cfg(target_os = "linux")]
+use crate::{transactions::Change, verify::Pin};
+
+pub struct Pacman {
+    context: Context,
+    #[cfg(target_os = "linux")]
+    aur: aur::AurClient,
+}
 
 impl Pacman {
-    pub fn new() -> Result<Self> {
-        Ok(Self {})
+    pub fn new(context: Context) -> Result<Self> {
+        Ok(Self {
+            context,
+            #[cfg(target_os = "linux")]
+            aur: aur::AurClient::from_env(),
+        })
+    }
+
+    /// Arguments passed to `pacman` to install `packages`,
+    /// with `-S` for repo packages or `-U` for package files.
+    fn pacman_args(
+        &self,
+        operation: &str,
+        options: &PacmanOptions,
+        packages: &[String],
+    ) -> Vec<String> {
+        // `--needed` skips packages that are already up to date
+        let mut args = vec![operation.to_string(), "--needed".to_string()];
+        if !self.context.interactive {
+            args.push("--noconfirm".to_string());
+        }
+        args.extend(options.location_args());
+        args.extend(options.pacman_args.iter().flatten().cloned());
+        args.extend(packages.iter().cloned());
+        args
+    }
+
+    fn pacman_invocation(
+        &self,
+        operation: &str,
+        options: &PacmanOptions,
+        packages: &[String],
+    ) -> Result<Invocation> {
+        let invocation = self.context.elevated("pacman")?;
+        Ok(invocation.args(self.pacman_args(operation, options, packages)))
+    }
+
+    /// Arguments passed to `pacman` to mark installed dependencies as explicitly installed.
+    fn explicit_args(&self, options: &PacmanOptions, packages: &[String]) -> Vec<String> {
+        let mut args = vec!["-D".to_string(), "--asexplicit".to_string()];
+        args.extend(options.location_args());
+        args.extend(packages.iter().cloned());
+        args
+    }
+
+    fn explicit_invocation(
+        &self,
+        options: &PacmanOptions,
+        packages: &[String],
+    ) -> Result<Invocation> {
+        let invocation = self.context.elevated("pacman")?;
+        Ok(invocation.args(self.explicit_args(options, packages)))
+    }
+
+    /// Command shown for a `pacman` invocation.
+    fn pacman_command(
+        &self,
+        operation: &str,
+        options: &PacmanOptions,
+        packages: &[String],
+    ) -> String {
+        // Shown without elevation if no tool is available, installing reports the error
+        let invocation = self
+            .pacman_invocation(operation, options, packages)
+            .unwrap_or_else(|_| {
+                Invocation::new("pacman").args(self.pacman_args(operation, options, packages))
+            });
+        invocation.to_string()
+    }
+
+    fn aur_invocation(
+        &self,
+        options: &PacmanOptions,
+        helper: &str,
+        packages: &[String],
+    ) -> Invocation {
+        self.context
+            .invocation(helper)
+            .args(self.aur_args(options, helper, packages))
+    }
+
+    /// Arguments passed to `helper` to install AUR `packages`.
+    fn aur_args(&self, options: &PacmanOptions, helper: &str, packages: &[String]) -> Vec<String> {
+        let mut args = vec!["-S".to_string(), "--needed".to_string()];
+        if !self.context.interactive {
+            args.push("--noconfirm".to_string());
+        }
+
+        // paru and yay call sudo on their own unless told otherwise
+        if let Privileges::Elevate(tool) = self.context.privileges
+            && tool != ElevationTool::Sudo
+            && matches!(helper, "paru" | "yay")
+        {
+            args.push("--sudo".to_string());
+            args.push(tool.to_string());
+        }
+
+        args.extend(options.aur_helper_args.iter().flatten().cloned());
+        args.extend(packages.iter().cloned());
+        args
     }
 }
 
@@ -24,9 +151,12 @@ impl Pacman {
 pub struct PacmanPackage {
     pub name: String,
     pub version: String,
+
+    /// Whether the package was installed explicitly rather than as a dependency.
+    pub explicit: bool,
 }
 
-#[derive(Deserialize, Debug, Clone, JsonSchema)]
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
 pub struct PacmanOptions {
     /// Packages installed using `pacman`
     pub repo: Option<Vec<String>>,
@@ -42,6 +172,712 @@ pub struct PacmanOptions {
 
     /// Force the usage of a specified AUR helper.
     pub force_aur_helper: Option<String>,
+
+    /// Base URL of the AUR or a mirror of it, used to look up packages and to clone them
+    /// when no AUR helper is installed.
+    ///
+    /// Defaults to the `AUR_URL` environment variable, or `https://aur.archlinux.org`.
+    pub aur_url: Option<String>,
+
+    /// Installation root, passed to `pacman` as `--root`.
+    ///
+    /// Useful for provisioning chroots and images.
+    /// If `dbpath` is not set, the database is looked up in `<root>/var/lib/pacman`.
+    pub root: Option<PathBuf>,
+
+    /// Database directory, passed to `pacman` as `--dbpath`.
+    pub dbpath: Option<PathBuf>,
+
+    /// Path to `pacman.conf`, passed to `pacman` as `--config`.
+    ///
+    /// Its `RootDir` and `DBPath` are used unless `root` or `dbpath` are set.
+    pub config: Option<PathBuf>,
+
+    /// If set to `true`, packages declared under the wrong list are installed
+    /// from where they are available, e.g. repo packages listed under `aur` with `pacman`.
+    pub auto_route: Option<bool>,
+
+    /// If set to `true`, PKGBUILDs are shown before building AUR packages
+    /// when no AUR helper is installed and chezpilot builds them itself.
+    pub review_pkgbuild: Option<bool>,
+
+    /// AUR package of a helper to build and install first when none is installed,
+    /// e.g. `paru` or `yay-bin`.
+    pub bootstrap_aur_helper: Option<String>,
+
+    /// Package archives installed using `pacman -U`, unless the same or a newer version is installed.
+    ///
+    /// Either paths relative to the configuration file or URLs, e.g.:
+    /// - `"packages/internal-tools-1.2.0-1-any.pkg.tar.zst"`
+    /// - `"https://example.com/internal-tools-1.2.0-1-any.pkg.tar.zst"`
+    /// - `{ file: "https://example.com/internal-tools-1.2.0-1-any.pkg.tar.zst", sha256: "3a5f..." }`
+    pub files: Option<Vec<PackageFile>>,
+
+    /// Third-party repositories added to `pacman.conf` before installing.
+    ///
+    /// Repositories of all batches are written to `pacman.d/chezpilot.conf` next to `pacman.conf`,
+    /// which is included from it. Whenever they change, the databases are synced
+    /// along with a full system upgrade (`pacman -Syu`), as installing from freshly synced databases
+    /// without upgrading would be a partial upgrade.
+    pub repositories: Option<Vec<PacmanRepository>>,
+
+    /// If set to `true`, declared packages that are installed as dependencies
+    /// are marked as explicitly installed, so that removing their dependents keeps them.
+    pub explicit: Option<bool>,
+}
+
+/// Package archive installed using `pacman -U`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(
+    untagged,
+    expecting = "a path or URL, or a `file` with only `sha256`, `signature` and `key` next to it"
+)]
+pub enum PackageFile {
+    Plain(String),
+    Pinned(PinnedFile),
+}
+
+impl PackageFile {
+    pub fn location(&self) -> &str {
+        match self {
+            PackageFile::Plain(file) | PackageFile::Pinned(PinnedFile { file, .. }) => file,
+        }
+    }
+}
+
+/// Package archive that has to match a checksum or signature before it is installed.
+///
+/// Unknown keys are rejected, so that a misspelled pin doesn't install the archive unverified.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PinnedFile {
+    /// Path relative to the configuration file or URL of the archive.
+    pub file: String,
+
+    /// Expected SHA-256 digest in hex, as printed by `sha256sum`.
+    pub sha256: Option<String>,
+
+    /// Detached minisign or SSH signature of the archive,
+    /// either a URL or a path relative to the configuration file.
+    pub signature: Option<String>,
+
+    /// Name of the key under `keys` that `signature` has to be made with.
+    pub key: Option<String>,
+}
+
+#[cfg(target_os = "linux")]
+impl PinnedFile {
+    fn pin(&self) -> Pin {
+        Pin {
+            sha256: self.sha256.clone(),
+            signature: self.signature.clone(),
+            key: self.key.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct PacmanRepository {
+    /// Name of the repository, e.g. `chaotic-aur`.
+    pub name: String,
+
+    /// `Server` URLs of the repository, tried in order.
+    pub servers: Vec<String>,
+
+    /// `SigLevel` of the repository, e.g. `Required DatabaseOptional`.
+    ///
+    /// The `SigLevel` from the `[options]` section is used if not set.
+    pub sig_level: Option<String>,
+
+    /// Keys imported into the pacman keyring and signed locally before the repository is added.
+    pub keys: Option<Vec<PacmanKey>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+pub struct PacmanKey {
+    /// Key ID or fingerprint.
+    pub id: String,
+
+    /// Key file, relative to the configuration file.
+    ///
+    /// The key is received from a keyserver if not set.
+    pub file: Option<String>,
+}
+
+impl PacmanOptions {
+    /// Paths or URLs of declared package files.
+    fn file_locations(&self) -> Vec<String> {
+        self.files
+            .iter()
+            .flatten()
+            .map(|file| file.location().to_string())
+            .collect()
+    }
+
+    /// Arguments pointing `pacman` at the configured installation.
+    fn location_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for (flag, path) in [
+            ("--root", &self.root),
+            ("--dbpath", &self.dbpath),
+            ("--config", &self.config),
+        ] {
+            if let Some(path) = path {
+                args.push(flag.to_string());
+                args.push(path.display().to_string());
+            }
+        }
+        args
+    }
+
+    /// Whether both options point at the same installation.
+    fn same_location(&self, other: &Self) -> bool {
+        self.root == other.root && self.dbpath == other.dbpath && self.config == other.config
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl PacmanOptions {
+    /// Packages of the local database, and groups of the sync databases with their members.
+    fn read_installed(&self) -> Result<(Vec<LocalPackage>, SyncGroups)> {
+        let alpm = self.alpm()?;
+
+        let packages = alpm
+            .localdb()
+            .pkgs()
+            .iter()
+            .map(|package| LocalPackage {
+                package: PacmanPackage {
+                    name: package.name().to_string(),
+                    version: package.version().to_string(),
+                    explicit: package.reason() == alpm::PackageReason::Explicit,
+                },
+                provides: package
+                    .provides()
+                    .iter()
+                    .map(|dep| dep.name().to_string())
+                    .collect(),
+                replaces: package
+                    .replaces()
+                    .iter()
+                    .map(|dep| dep.name().to_string())
+                    .collect(),
+                depends: package
+                    .depends()
+                    .iter()
+                    .map(|dep| dep.name().to_string())
+                    .collect(),
+            })
+            .collect();
+
+        let mut groups = Vec::new();
+        for db in alpm.syncdbs() {
+            for group in db.groups().into_diagnostic()? {
+                let members = group
+                    .packages()
+                    .iter()
+                    .map(|member| member.name().to_string());
+                groups.push((group.name().to_string(), members.collect()));
+            }
+        }
+
+        Ok((packages, groups))
+    }
+
+    /// Opens the local and sync databases of the configured installation,
+    /// resolving paths the same way `pacman` does.
+    fn alpm(&self) -> Result<Alpm> {
+        use std::path::Path;
+
+        let conf = conf::PacmanConf::read(self.config.as_deref())?;
+
+        let root = self
+            .root
+            .clone()
+            .or(conf.root_dir)
+            .unwrap_or_else(|| PathBuf::from("/"));
+        let dbpath = match (&self.dbpath, conf.db_path, &self.root) {
+            (Some(dbpath), _, _) => dbpath.clone(),
+            (None, Some(dbpath), _) => dbpath,
+            (None, None, Some(root)) => root.join("var/lib/pacman"),
+            (None, None, None) => PathBuf::from("/var/lib/pacman"),
+        };
+
+        let path = |path: &Path| path.to_string_lossy().into_owned();
+        let alpm = Alpm::new(path(&root), path(&dbpath)).into_diagnostic()?;
+
+        // Sync databases are only read to look up package names, so their signatures are not checked
+        for repository in conf.repositories {
+            alpm.register_syncdb(repository, SigLevel::NONE)
+                .into_diagnostic()?;
+        }
+
+        Ok(alpm)
+    }
+
+    /// Looks up `declared` names in the sync databases.
+    ///
+    /// Returns `None` if no sync database was synced yet, or declared repositories
+    /// were not added yet, as names from them would be unknown.
+    fn sync_lookup(&self, declared: &[String]) -> Result<Option<SyncLookup>> {
+        let conf = conf::PacmanConf::read(self.config.as_deref())?;
+        let pending = self
+            .repositories
+            .iter()
+            .flatten()
+            .any(|repository| !conf.repositories.contains(&repository.name));
+        if pending {
+            return Ok(None);
+        }
+
+        let alpm = self.alpm()?;
+        let dbs = alpm.syncdbs();
+        if dbs.iter().all(|db| db.pkgs().is_empty()) {
+            return Ok(None);
+        }
+
+        let found = declared
+            .iter()
+            .filter(|name| {
+                dbs.find_satisfier(name.as_str()).is_some()
+                    || dbs.iter().any(|db| db.group(name.as_str()).is_ok())
+            })
+            .cloned()
+            .collect();
+        let names = dbs
+            .iter()
+            .flat_map(|db| db.pkgs())
+            .map(|package| package.name().to_string())
+            .collect();
+
+        Ok(Some(SyncLookup { found, names }))
+    }
+
+    /// Real names and sync database versions of `names`, with groups expanded to their members,
+    /// i.e. the packages `pacman -S` installs for them.
+    fn sync_versions(&self, names: &[String]) -> Result<Vec<(String, Option<String>)>> {
+        let alpm = self.alpm()?;
+        let dbs = alpm.syncdbs();
+
+        let mut versions = Vec::new();
+        for name in names {
+            let packages: Vec<_> = match dbs.find_satisfier(name.as_str()) {
+                Some(package) => vec![package],
+                None => dbs
+                    .iter()
+                    .find_map(|db| db.group(name.as_str()).ok())
+                    .map(|group| group.packages().iter().collect())
+                    .unwrap_or_default(),
+            };
+            if packages.is_empty() {
+                versions.push((name.clone(), None));
+            }
+            for package in packages {
+                let entry = (
+                    package.name().to_string(),
+                    Some(package.version().to_string()),
+                );
+                if !versions.contains(&entry) {
+                    versions.push(entry);
+                }
+            }
+        }
+
+        Ok(versions)
+    }
+
+    /// Names of `dependencies` that are installed or available from the sync databases.
+    fn available(&self, dependencies: &[String]) -> Result<HashSet<String>> {
+        let alpm = self.alpm()?;
+        let installed = alpm.localdb().pkgs();
+        let dbs = alpm.syncdbs();
+
+        let available = dependencies
+            .iter()
+            .filter(|name| {
+                installed.find_satisfier(name.as_str()).is_some()
+                    || dbs.find_satisfier(name.as_str()).is_some()
+            })
+            .cloned()
+            .collect();
+
+        Ok(available)
+    }
+
+    /// Moves packages to the list of the installer that can actually install them.
+    fn routed(mut self, sync: &SyncLookup) -> Self {
+        let (repo, to_aur): (Vec<_>, Vec<_>) = self
+            .repo
+            .take()
+            .into_iter()
+            .flatten()
+            .partition(|name| sync.found.contains(name));
+        let (to_repo, aur): (Vec<_>, Vec<_>) = self
+            .aur
+            .take()
+            .into_iter()
+            .flatten()
+            .partition(|name| sync.found.contains(name));
+
+        self.repo = Some(repo.into_iter().chain(to_repo).collect());
+        self.aur = Some(aur.into_iter().chain(to_aur).collect());
+        self
+    }
+}
+
+/// Names from the sync databases, used to tell which installer a package belongs to.
+#[cfg(target_os = "linux")]
+struct SyncLookup {
+    /// Declared names satisfied by a sync package or group.
+    found: HashSet<String>,
+
+    /// Names of all sync packages, used for suggestions.
+    names: Vec<String>,
+}
+
+/// Groups of the sync databases with the names of their members.
+#[cfg(target_os = "linux")]
+type SyncGroups = Vec<(String, Vec<String>)>;
+
+/// Package of the local database with the names it satisfies besides its own.
+#[cfg(target_os = "linux")]
+struct LocalPackage {
+    package: PacmanPackage,
+    provides: Vec<String>,
+    replaces: Vec<String>,
+
+    /// Names of the packages it depends on, without version constraints.
+    depends: Vec<String>,
+}
+
+/// Keys installed `packages` by every name the installation satisfies, so that virtual packages
+/// and packages replaced by variants like `-git` don't count as missing.
+///
+/// A group of the sync databases, given with its members, is satisfied once all of its members are,
+/// like `pacman -S --needed` sees it.
+#[cfg(target_os = "linux")]
+fn index_installed(
+    packages: Vec<LocalPackage>,
+    groups: &[(String, Vec<String>)],
+) -> HashMap<String, PacmanPackage> {
+    let mut installed = HashMap::new();
+    for LocalPackage {
+        package,
+        provides,
+        replaces,
+        ..
+    } in packages
+    {
+        for name in provides.iter().chain(&replaces).chain([&package.name]) {
+            installed
+                .entry(name.clone())
+                .or_insert_with(|| package.clone());
+        }
+    }
+
+    for (group, members) in groups {
+        if let Some(member) = members.first()
+            && members.iter().all(|member| installed.contains_key(member))
+        {
+            let entry = installed[member].clone();
+            installed.entry(group.clone()).or_insert(entry);
+        }
+    }
+
+    installed
+}
+
+/// Names of installed `targets` and of the packages they depend on, directly or not.
+///
+/// Groups among `targets` stand for their members.
+#[cfg(target_os = "linux")]
+fn with_dependencies(
+    packages: &[LocalPackage],
+    groups: &[(String, Vec<String>)],
+    targets: &[String],
+) -> HashSet<String> {
+    // Own names take precedence over names provided or replaced by other packages
+    let mut satisfiers: HashMap<&str, &LocalPackage> = HashMap::new();
+    for local in packages {
+        satisfiers.insert(&local.package.name, local);
+    }
+    for local in packages {
+        for name in local.provides.iter().chain(&local.replaces) {
+            satisfiers.entry(name).or_insert(local);
+        }
+    }
+
+    let mut pending: Vec<&str> = targets.iter().map(String::as_str).collect();
+    for (group, members) in groups {
+        if targets.contains(group) {
+            pending.extend(members.iter().map(String::as_str));
+        }
+    }
+
+    let mut names = HashSet::new();
+    while let Some(name) = pending.pop() {
+        if let Some(local) = satisfiers.get(name)
+            && names.insert(local.package.name.clone())
+        {
+            pending.extend(local.depends.iter().map(String::as_str));
+        }
+    }
+
+    names
+}
+
+/// Real names and installed versions of `declared` packages, with groups expanded to their members.
+#[cfg(target_os = "linux")]
+fn resolve_versions(
+    declared: &[String],
+    installed: &HashMap<String, PacmanPackage>,
+    groups: &[(String, Vec<String>)],
+) -> Vec<(String, Option<String>)> {
+    let mut versions = Vec::new();
+
+    for name in declared {
+        // A package named like a group is what `pacman -S` installs for that name
+        let is_package = installed
+            .get(name)
+            .is_some_and(|package| package.name == *name);
+        let members = match groups.iter().find(|(group, _)| group == name) {
+            Some((_, members)) if !is_package => members.as_slice(),
+            _ => std::slice::from_ref(name),
+        };
+
+        for member in members {
+            let entry = match installed.get(member) {
+                Some(package) => (package.name.clone(), Some(package.version.clone())),
+                None => (member.clone(), None),
+            };
+            if !versions.contains(&entry) {
+                versions.push(entry);
+            }
+        }
+    }
+
+    versions
+}
+
+/// Declared packages of `options` that are installed, but only as dependencies.
+#[cfg(target_os = "linux")]
+fn installed_as_dependencies(
+    installed: &HashMap<String, PacmanPackage>,
+    options: &PacmanOptions,
+) -> Vec<String> {
+    // Entries keyed by a provided name or a group are not the declared package itself
+    options
+        .repo
+        .iter()
+        .chain(&options.aur)
+        .flatten()
+        .filter(|name| {
+            installed
+                .get(*name)
+                .is_some_and(|package| package.name == **name && !package.explicit)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Whether the package file described by `info` is not installed or newer than the installed version,
+/// comparing versions with `vercmp` like `pacman` does.
+#[cfg(target_os = "linux")]
+fn is_outdated(
+    installed: &HashMap<String, PacmanPackage>,
+    info: &files::PkgInfo,
+    vercmp: impl Fn(&str, &str) -> std::cmp::Ordering,
+) -> bool {
+    // Entries keyed by a provided name belong to a different package
+    installed
+        .get(&info.name)
+        .filter(|package| package.name == info.name)
+        .is_none_or(|package| vercmp(&package.version, &info.version).is_lt())
+}
+
+#[cfg(target_os = "linux")]
+impl Pacman {
+    /// Local paths of declared package files that are not installed or outdated.
+    ///
+    /// Files given as URLs are downloaded to read their version.
+    async fn missing_files(
+        &self,
+        options: &PacmanOptions,
+        installed: &HashMap<String, PacmanPackage>,
+    ) -> Result<Vec<PackageFile>> {
+        let mut missing = Vec::new();
+        for entry in options.files.iter().flatten() {
+            let path = files::locate(entry.location(), &self.context.config_dir).await?;
+            let info = files::read_pkginfo(&path)?;
+            if !is_outdated(installed, &info, |a, b| alpm::vercmp(a, b)) {
+                continue;
+            }
+
+            // Pins are kept, so that `verify` checks the archive that is installed
+            let file = path.display().to_string();
+            missing.push(match entry {
+                PackageFile::Plain(_) => PackageFile::Plain(file),
+                PackageFile::Pinned(pinned) => PackageFile::Pinned(PinnedFile {
+                    file,
+                    ..pinned.clone()
+                }),
+            });
+        }
+
+        Ok(missing)
+    }
+
+    /// Package names and versions of declared package files, read from their `.PKGINFO`.
+    async fn file_infos(&self, options: &PacmanOptions) -> Result<Vec<files::PkgInfo>> {
+        let mut infos = Vec::new();
+        for entry in options.files.iter().flatten() {
+            let path = files::locate(entry.location(), &self.context.config_dir).await?;
+            infos.push(files::read_pkginfo(&path)?);
+        }
+        Ok(infos)
+    }
+
+    /// Builds AUR `packages` with `makepkg`, for systems without an AUR helper.
+    async fn install_with_makepkg(
+        &self,
+        options: &PacmanOptions,
+        packages: &[String],
+    ) -> Result<()> {
+        use crate::errors::MakepkgAsRoot;
+
+        if self.context.privileges == Privileges::Root {
+            return Err(MakepkgAsRoot.into());
+        }
+        let Some(build_dir) = makepkg::default_build_dir() else {
+            miette::bail!("Unable to find a cache directory to build AUR packages in");
+        };
+
+        let aur = self.aur_client(options);
+        let makepkg = makepkg::Makepkg {
+            runner: self.context.runner.as_ref(),
+            aur: &aur,
+            build_dir,
+            interactive: self.context.interactive,
+            review: options.review_pkgbuild == Some(true),
+        };
+        makepkg
+            .install(packages, |dependencies| options.available(dependencies))
+            .await
+    }
+
+    /// Checks declared repo and AUR packages against the sync databases, if they could be read,
+    /// and the AUR.
+    async fn check_declared(
+        &self,
+        options: &PacmanOptions,
+        sync: Option<&SyncLookup>,
+    ) -> Vec<PackageProblem> {
+        use owo_colors::OwoColorize;
+        use tracing::{info, warn};
+
+        let in_sync = |name: &String| sync.map(|sync| sync.found.contains(name));
+
+        let repo = options.repo.iter().flatten();
+        let aur = options.aur.iter().flatten();
+
+        // Repo packages missing from the sync databases may be AUR packages in the wrong list
+        let unsynced: Vec<String> = repo
+            .filter(|name| in_sync(name) == Some(false))
+            .cloned()
+            .collect();
+        let lookup: Vec<String> = aur
+            .clone()
+            .filter(|name| in_sync(name) != Some(true))
+            .cloned()
+            .chain(unsynced.iter().cloned())
+            .collect();
+        let aur_client = self.aur_client(options);
+        // An unreachable AUR only skips the checks that need it, it may be down or the machine offline
+        let existing = if lookup.is_empty() {
+            Some(HashSet::new())
+        } else {
+            match aur_client.existing(&lookup).await {
+                Ok(existing) => Some(existing),
+                Err(error) => {
+                    warn!("Skipping the check of AUR packages: {error:?}");
+                    None
+                }
+            }
+        };
+        let in_aur = |name: &String| existing.as_ref().map(|existing| existing.contains(name));
+
+        let mut misplaced = Vec::new();
+        let mut problems = Vec::new();
+        for name in unsynced {
+            match in_aur(&name) {
+                Some(true) => misplaced.push(MisplacedPackage {
+                    name,
+                    declared: "repo",
+                    available: "aur",
+                }),
+                Some(false) => {
+                    let names = sync.iter().flat_map(|sync| &sync.names);
+                    let suggestion = suggest(&name, names.map(String::as_str));
+                    problems.push(PackageProblem::from(PackageNotFound {
+                        name,
+                        origin: "the sync databases",
+                        suggestion,
+                    }));
+                }
+                None => {}
+            }
+        }
+
+        for name in aur {
+            if in_sync(name) == Some(true) {
+                misplaced.push(MisplacedPackage {
+                    name: name.clone(),
+                    declared: "aur",
+                    available: "repo",
+                });
+            } else if in_aur(name) == Some(false) {
+                problems.push(PackageProblem::from(PackageNotFound {
+                    name: name.clone(),
+                    origin: "the AUR",
+                    suggestion: self.suggest_aur(&aur_client, name).await,
+                }));
+            }
+        }
+
+        // Routed packages are installed from where they are available, so they are only worth a note
+        if options.auto_route == Some(true) {
+            for package in misplaced {
+                info!(
+                    "{} is declared under `{}` but available from `{}`, it is routed there",
+                    package.name.blue().bold(),
+                    package.declared,
+                    package.available
+                );
+            }
+        } else {
+            problems.extend(misplaced.into_iter().map(PackageProblem::from));
+        }
+
+        problems
+    }
+
+    /// Client of the AUR configured by `options`, or the one from the environment.
+    fn aur_client(&self, options: &PacmanOptions) -> aur::AurClient {
+        match &options.aur_url {
+            Some(url) => aur::AurClient::new(url.as_str()),
+            None => self.aur.clone(),
+        }
+    }
+
+    /// Suggests an AUR package similar to `name`.
+    async fn suggest_aur(&self, aur: &aur::AurClient, name: &str) -> Option<String> {
+        // The AUR only searches by substring, a prefix still matches typos near the end
+        let length = name.chars().count().div_ceil(2).max(2);
+        let term: String = name.chars().take(length).collect();
+        let candidates = aur.search(&term).await.ok()?;
+
+        suggest(name, candidates.iter().map(String::as_str))
+    }
 }
 
 #[async_trait]
@@ -53,26 +889,288 @@ impl PackageManager for Pacman {
     type Package = PacmanPackage;
 
     #[cfg(target_os = "linux")]
-    async fn get_installed(&self) -> Result<HashMap<String, Self::Package>> {
-        let alpm = Alpm::new("/", "/var/lib/pacman").into_diagnostic()?;
-        let db = alpm.localdb();
+    async fn get_installed(
+        &self,
+        options: &Self::Options,
+    ) -> Result<HashMap<String, Self::Package>> {
+        let (packages, groups) = options.read_installed()?;
+        Ok(index_installed(packages, &groups))
+    }
+
+    /// Versions are recorded under real package names, with groups expanded to their members
+    /// and package files named by their `.PKGINFO`, so that they match what `pacman -Q` lists.
+    #[cfg(target_os = "linux")]
+    async fn installed_versions(
+        &self,
+        options: &Self::Options,
+    ) -> Result<Vec<(String, Option<String>)>> {
+        let (packages, groups) = options.read_installed()?;
+        let installed = index_installed(packages, &groups);
+
+        let mut declared: Vec<String> = options
+            .repo
+            .iter()
+            .chain(&options.aur)
+            .flatten()
+            .cloned()
+            .collect();
+        let files = self.file_infos(options).await?;
+        declared.extend(files.into_iter().map(|info| info.name));
 
-        let packages = db
+        Ok(resolve_versions(&declared, &installed, &groups))
+    }
+
+    /// Repository packages are at the version of the sync databases and files at the one
+    /// of their `.PKGINFO`, while AUR packages are only known once they are built.
+    #[cfg(target_os = "linux")]
+    async fn candidate_versions(
+        &self,
+        missing: &Self::Options,
+    ) -> Result<Vec<(String, Option<String>)>> {
+        let repo = missing.repo.clone().unwrap_or_default();
+        let mut versions = missing.sync_versions(&repo)?;
+        for name in missing.aur.iter().flatten() {
+            versions.push((name.clone(), None));
+        }
+        for info in self.file_infos(missing).await? {
+            versions.push((info.name, Some(info.version)));
+        }
+        Ok(versions)
+    }
+
+    fn version(&self, package: &Self::Package) -> Option<String> {
+        Some(package.version.clone())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn snapshot(&self, options: &Self::Options) -> Result<Option<HashMap<String, String>>> {
+        let alpm = options.alpm()?;
+        let snapshot = alpm
+            .localdb()
             .pkgs()
             .iter()
-            .map(|package| {
-                // dbg!(&package.origin());
-                (
-                    package.name().to_string(),
-                    PacmanPackage {
-                        name: package.name().to_string(),
-                        version: package.version().to_string(),
-                    },
-                )
-            })
+            .map(|package| (package.name().to_string(), package.version().to_string()))
+            .collect();
+        Ok(Some(snapshot))
+    }
+
+    /// Targets of `options` and their dependencies, so that packages other lanes install meanwhile,
+    /// e.g. from a script, are not logged as part of the transaction.
+    /// Changes those lanes make to the same packages can't be told apart and are logged.
+    #[cfg(target_os = "linux")]
+    async fn affected(&self, options: &Self::Options) -> Result<Option<HashSet<String>>> {
+        let (packages, groups) = options.read_installed()?;
+
+        let mut targets: Vec<String> = options
+            .repo
+            .iter()
+            .chain(&options.aur)
+            .flatten()
+            .cloned()
             .collect();
+        let files = self.file_infos(options).await?;
+        targets.extend(files.into_iter().map(|info| info.name));
+
+        Ok(Some(with_dependencies(&packages, &groups, &targets)))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn rollback(&self, options: &Self::Options, changes: &[Change]) -> Result<()> {
+        self.revert(options, changes).await
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn find_missing(&self, options: &Self::Options) -> Result<(Self::Options, usize)> {
+        let installed = self.get_installed(options).await?;
+        let files = self.missing_files(options, &installed).await?;
+        let (mut missing, mut count) = self.filter_missing(installed, options)?;
+
+        // Files are checked by their version, which `filter_missing` can't see
+        if options.files.is_some() {
+            count += files.len();
+            missing.files = Some(files);
+        }
+
+        if options.auto_route != Some(true) || count == 0 {
+            return Ok((missing, count));
+        }
+
+        match missing.sync_lookup(&self.packages(&missing))? {
+            Some(sync) => Ok((missing.routed(&sync), count)),
+            None => Ok((missing, count)),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn validate(&self, options: &Self::Options) -> Result<Vec<PackageProblem>> {
+        use tracing::warn;
+
+        let declared: Vec<String> = options
+            .repo
+            .iter()
+            .chain(&options.aur)
+            .flatten()
+            .cloned()
+            .collect();
+        if declared.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let sync = match options.sync_lookup(&declared) {
+            Ok(Some(sync)) => Some(sync),
+            Ok(None) => {
+                warn!("Pacman databases are not synced yet, skipping the check of repo packages");
+                None
+            }
+            Err(error) => {
+                warn!("Skipping the check of repo packages: {error}");
+                None
+            }
+        };
+        Ok(self.check_declared(options, sync.as_ref()).await)
+    }
+
+    fn packages(&self, options: &Self::Options) -> Vec<String> {
+        options
+            .repo
+            .iter()
+            .chain(&options.aur)
+            .flatten()
+            .cloned()
+            .chain(options.file_locations())
+            .collect()
+    }
+
+    fn commands(&self, options: &Self::Options) -> Vec<String> {
+        let mut commands = Vec::new();
+
+        if let Some(packages) = &options.repo
+            && !packages.is_empty()
+        {
+            commands.push(self.pacman_command("-S", options, packages));
+        }
+
+        let files = options.file_locations();
+        if !files.is_empty() {
+            commands.push(self.pacman_command("-U", options, &files));
+        }
+
+        if let Some(packages) = &options.aur
+            && !packages.is_empty()
+        {
+            // The helper is only detected right before installing
+            let helper = options
+                .force_aur_helper
+                .clone()
+                .or_else(|| std::env::var("AUR_HELPER").ok())
+                .unwrap_or_else(|| "<aur-helper>".to_string());
+            let invocation = self.aur_invocation(options, &helper, packages);
+            commands.push(invocation.to_string());
+        }
+
+        commands
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn find_remarks(&self, options: &Self::Options) -> Result<Vec<String>> {
+        if options.explicit != Some(true) {
+            return Ok(Vec::new());
+        }
+        let installed = self.get_installed(options).await?;
+        Ok(installed_as_dependencies(&installed, options))
+    }
+
+    fn remark_commands(&self, options: &Self::Options, packages: &[String]) -> Vec<String> {
+        let invocation = self
+            .explicit_invocation(options, packages)
+            .unwrap_or_else(|_| {
+                Invocation::new("pacman").args(self.explicit_args(options, packages))
+            });
+        vec![invocation.to_string()]
+    }
+
+    async fn remark(&self, options: &Self::Options, packages: &[String]) -> Result<()> {
+        use owo_colors::OwoColorize;
+        use tracing::info;
+
+        let invocation = self.explicit_invocation(options, packages)?;
+        let status = self
+            .context
+            .runner
+            .status(&invocation)
+            .await
+            .into_diagnostic()?;
+        if !status.success() {
+            miette::bail!("pacman exited with status: {}", status);
+        }
+
+        let separator = ", ".dimmed().to_string();
+        info!(
+            "Marked {} as explicitly installed",
+            packages.join(&separator).blue()
+        );
+        Ok(())
+    }
+
+    /// Checks pinned package files, dropping downloads that don't match from the cache.
+    #[cfg(target_os = "linux")]
+    async fn verify(&self, options: &Self::Options) -> Result<()> {
+        for entry in options.files.iter().flatten() {
+            let PackageFile::Pinned(pinned) = entry else {
+                continue;
+            };
+            let path = files::locate(&pinned.file, &self.context.config_dir).await?;
+            let content = tokio::fs::read(&path).await.into_diagnostic()?;
+            if let Err(error) = pinned
+                .pin()
+                .check(&self.context, &pinned.file, &content)
+                .await
+            {
+                // Dropped from the cache, so that the next run downloads it again
+                if files::is_downloaded(&path) {
+                    tokio::fs::remove_file(&path).await.ok();
+                }
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
 
-        Ok(packages)
+    fn needs_root(&self, options: &Self::Options) -> bool {
+        // AUR helpers elevate on their own to install built packages
+        !self.packages(options).is_empty()
+    }
+
+    fn merge(&self, combined: &mut Self::Options, other: &Self::Options) -> bool {
+        if !combined.same_location(other)
+            || combined.pacman_args != other.pacman_args
+            || combined.aur_helper_args != other.aur_helper_args
+            || combined.force_aur_helper != other.force_aur_helper
+            || combined.aur_url != other.aur_url
+            || combined.bootstrap_aur_helper != other.bootstrap_aur_helper
+            || combined.repositories != other.repositories
+        {
+            return false;
+        }
+
+        for (target, source) in [
+            (&mut combined.repo, &other.repo),
+            (&mut combined.aur, &other.aur),
+        ] {
+            let target = target.get_or_insert_with(Vec::new);
+            for package in source.iter().flatten() {
+                if !target.contains(package) {
+                    target.push(package.clone());
+                }
+            }
+        }
+        let files = combined.files.get_or_insert_with(Vec::new);
+        for file in other.files.iter().flatten() {
+            if !files.contains(file) {
+                files.push(file.clone());
+            }
+        }
+        true
     }
 
     fn filter_missing(
@@ -105,20 +1203,28 @@ impl PackageManager for Pacman {
         ))
     }
 
+    #[cfg(target_os = "linux")]
+    async fn preparation(&self, options: &[&Self::Options]) -> Result<Vec<String>> {
+        self.repository_steps(options).await
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn prepare(&self, options: &[&Self::Options]) -> Result<()> {
+        self.add_repositories(options).await
+    }
+
     #[cfg(target_os = "linux")]
     async fn install(&self, options: Self::Options) -> Result<()> {
-        use crate::package_managers::pacman::utils::select_aur_helper;
+        use crate::package_managers::pacman::utils::{
+            AurDetectionError, AurHelper, select_aur_helper,
+        };
         use miette::Context;
         use owo_colors::OwoColorize;
-        use tokio::process::Command;
         use tracing::info;
 
-        dbg!(&options);
-
         if let Some(repo_packages) = &options.repo
             && !repo_packages.is_empty()
         {
-            use std::process::Stdio;
             let separator = ",".dimmed().to_string();
             info!(
                 packages = repo_packages.join(&separator),
@@ -126,16 +1232,29 @@ impl PackageManager for Pacman {
                 repo_packages.len().blue().bold()
             );
 
-            let status = Command::new("sudo")
-                .arg("pacman")
-                .arg("-S")
-                .arg("--needed") // Skip packages that are already up to date
-                .args(options.pacman_args.unwrap_or_default())
-                .args(repo_packages)
-                .stdin(Stdio::inherit())
-                .stdout(Stdio::inherit())
-                .stderr(Stdio::inherit())
-                .status()
+            let invocation = self.pacman_invocation("-S", &options, repo_packages)?;
+            let status = self
+                .context
+                .runner
+                .status(&invocation)
+                .await
+                .into_diagnostic()
+                .wrap_err("Failed to execute pacman")?;
+
+            if !status.success() {
+                miette::bail!("pacman exited with status: {}", status);
+            }
+        }
+
+        let files = options.file_locations();
+        if !files.is_empty() {
+            info!("Installing {} package files", files.len().blue().bold());
+
+            let invocation = self.pacman_invocation("-U", &options, &files)?;
+            let status = self
+                .context
+                .runner
+                .status(&invocation)
                 .await
                 .into_diagnostic()
                 .wrap_err("Failed to execute pacman")?;
@@ -148,24 +1267,39 @@ impl PackageManager for Pacman {
         if let Some(aur_packages) = &options.aur
             && !aur_packages.is_empty()
         {
-            let helper = select_aur_helper(options.force_aur_helper.clone())
-                .await
-                .into_diagnostic()?;
+            let helper = match select_aur_helper(
+                self.context.runner.as_ref(),
+                options.force_aur_helper.clone(),
+                options.bootstrap_aur_helper.clone(),
+            )
+            .await
+            {
+                Ok(AurHelper::Installed(helper)) => helper,
+                Ok(AurHelper::Bootstrap { package, helper }) => {
+                    info!("Bootstrapping {}", package.blue().bold());
+                    self.install_with_makepkg(&options, &[package]).await?;
+                    helper
+                }
+                Err(AurDetectionError::AurHelperNotDetected(_)) => {
+                    info!(
+                        "No AUR helper found, building with {}",
+                        "makepkg".blue().bold()
+                    );
+                    return self.install_with_makepkg(&options, aur_packages).await;
+                }
+                Err(error) => return Err(error).into_diagnostic(),
+            };
 
             info!("Using {}", helper.blue().bold());
 
-            let status = Command::new(&helper)
-                .arg("-S")
-                .arg("--needed")
-                .args(options.aur_helper_args.unwrap_or_default())
-                .args(aur_packages)
-                .stdin(std::process::Stdio::inherit())
-                .stdout(std::process::Stdio::inherit())
-                .stderr(std::process::Stdio::inherit())
-                .status()
+            let invocation = self.aur_invocation(&options, &helper, aur_packages);
+            let status = self
+                .context
+                .runner
+                .status(&invocation)
                 .await
                 .into_diagnostic()
-                .wrap_err("Failed to execute {helper}")?;
+                .wrap_err_with(|| format!("Failed to execute {helper}"))?;
 
             if !status.success() {
                 miette::bail!("{helper} exited with status: {}", status);
@@ -175,3 +1309,729 @@ impl PackageManager for Pacman {
         Ok(())
     }
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        errors::VerificationFailed,
+        package_managers::PackageManagerConfig,
+        runner::RecordingRunner,
+        transactions::{self, RunLog, Transaction},
+        verify::{self, Keys},
+    };
+
+    fn pacman(privileges: Privileges, interactive: bool) -> (Pacman, Arc<RecordingRunner>) {
+        let runner = Arc::new(RecordingRunner::default());
+        let context = Context {
+            privileges,
+            interactive,
+            runner: runner.clone(),
+            config_dir: PathBuf::from("/home/user/dotfiles"),
+            keys: Keys::default(),
+        };
+        (Pacman::new(context).unwrap(), runner)
+    }
+
+    fn options(yaml: &str) -> PacmanOptions {
+        serde_yaml::from_str(yaml).expect("Failed to parse options")
+    }
+
+    fn invocation(program: &str, args: &[&str], stdin: bool) -> Invocation {
+        Invocation::new(program)
+            .args(args.iter().copied())
+            .stdin(stdin)
+    }
+
+    #[tokio::test]
+    async fn installs_repo_packages_with_sudo() {
+        let (pacman, runner) = pacman(Privileges::Elevate(ElevationTool::Sudo), true);
+
+        let options = options("{ repo: [git, base-devel], pacman_args: [--color=never] }");
+        pacman.install(options).await.unwrap();
+
+        assert_eq!(
+            runner.invocations(),
+            [invocation(
+                "sudo",
+                &[
+                    "pacman",
+                    "-S",
+                    "--needed",
+                    "--color=never",
+                    "git",
+                    "base-devel"
+                ],
+                true
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn installs_into_custom_root() {
+        let (pacman, runner) = pacman(Privileges::Root, false);
+
+        let options = options("{ repo: [base], root: /mnt, config: /mnt/etc/pacman.conf }");
+        pacman.install(options).await.unwrap();
+
+        assert_eq!(
+            runner.invocations(),
+            [invocation(
+                "pacman",
+                &[
+                    "-S",
+                    "--needed",
+                    "--noconfirm",
+                    "--root",
+                    "/mnt",
+                    "--config",
+                    "/mnt/etc/pacman.conf",
+                    "base"
+                ],
+                false
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn suggests_similar_aur_packages() {
+        use wiremock::{
+            Mock, MockServer, ResponseTemplate,
+            matchers::{path, query_param},
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(path("/rpc/v5/info"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "results": [{ "Name": "paru-bin" }],
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(path("/rpc/v5/search/visual-stud"))
+            .and(query_param("by", "name"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "results": [{ "Name": "visual-studio-code-bin" }, { "Name": "vis-git" }],
+            })))
+            .mount(&server)
+            .await;
+
+        let sync = SyncLookup {
+            found: HashSet::new(),
+            names: vec!["git".to_string()],
+        };
+
+        let (mut pacman, _) = pacman(Privileges::Root, false);
+        pacman.aur = aur::AurClient::new(server.uri());
+
+        let options = options("{ aur: [paru-bin, visual-studio-code-bim] }");
+        let problems = pacman.check_declared(&options, Some(&sync)).await;
+
+        let [PackageProblem::NotFound(unknown)] = &problems[..] else {
+            panic!("Expected a single unknown package, got {problems:?}");
+        };
+        assert_eq!(unknown.name, "visual-studio-code-bim");
+        assert_eq!(
+            unknown.suggestion.as_deref(),
+            Some("Did you mean \"visual-studio-code-bin\"?")
+        );
+    }
+
+    #[tokio::test]
+    async fn resolves_real_names_of_installed_packages() {
+        let packages = vec![
+            local("neovim-git", "0.11.0.r1-1", &["neovim"], &[]),
+            local("autoconf", "2.72-1", &[], &[]),
+            local("automake", "1.17-1", &[], &[]),
+            local("internal-tools", "1.2.0-1", &[], &[]),
+        ];
+        let groups = [(
+            "base-devel".to_string(),
+            vec!["autoconf".to_string(), "automake".to_string()],
+        )];
+        let installed = index_installed(packages, &groups);
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = "internal-tools-1.2.0-1-any.pkg.tar.zst";
+        files::write_package_file(&dir.path().join(file), "internal-tools", "1.2.0-1");
+
+        let (mut pacman, _) = pacman(Privileges::Root, false);
+        pacman.context.config_dir = dir.path().to_path_buf();
+        let options = options(&format!(
+            "{{ repo: [neovim, base-devel, zsh], files: [{file}] }}"
+        ));
+
+        let mut declared = options.repo.clone().unwrap();
+        let files = pacman.file_infos(&options).await.unwrap();
+        declared.extend(files.into_iter().map(|info| info.name));
+        let version =
+            |name: &str, version: Option<&str>| (name.to_string(), version.map(str::to_string));
+        assert_eq!(
+            resolve_versions(&declared, &installed, &groups),
+            [
+                version("neovim-git", Some("0.11.0.r1-1")),
+                version("autoconf", Some("2.72-1")),
+                version("automake", Some("1.17-1")),
+                version("zsh", None),
+                version("internal-tools", Some("1.2.0-1")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_aur_checks_when_unreachable() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers::path};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/rpc/v5/info"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "results": [],
+            })))
+            .mount(&server)
+            .await;
+        let sync = SyncLookup {
+            found: HashSet::new(),
+            names: vec!["git".to_string()],
+        };
+
+        let (pacman, _) = pacman(Privileges::Root, false);
+        let mirror = options(&format!(
+            "{{ aur: [paru-bim], aur_url: '{}' }}",
+            server.uri()
+        ));
+        let problems = pacman.check_declared(&mirror, Some(&sync)).await;
+        assert!(matches!(&problems[..], [PackageProblem::NotFound(_)]));
+
+        // Nothing listens on the discard port
+        let offline = options("{ aur: [paru-bim], aur_url: 'http://127.0.0.1:9' }");
+        let problems = pacman.check_declared(&offline, Some(&sync)).await;
+        assert!(problems.is_empty(), "{problems:?}");
+    }
+
+    #[tokio::test]
+    async fn tolerates_misplaced_packages_when_routed() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers::path};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/rpc/v5/info"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "results": [{ "Name": "paru-bin" }],
+            })))
+            .mount(&server)
+            .await;
+        let sync = SyncLookup {
+            found: HashSet::from(["git".to_string()]),
+            names: vec!["git".to_string()],
+        };
+
+        let (pacman, _) = pacman(Privileges::Root, false);
+        let declared = format!("repo: [paru-bin], aur: [git], aur_url: '{}'", server.uri());
+
+        let strict = options(&format!("{{ {declared} }}"));
+        let problems = pacman.check_declared(&strict, Some(&sync)).await;
+        assert!(
+            matches!(
+                &problems[..],
+                [PackageProblem::Misplaced(_), PackageProblem::Misplaced(_)]
+            ),
+            "{problems:?}"
+        );
+
+        let routed = options(&format!("{{ {declared}, auto_route: true }}"));
+        let problems = pacman.check_declared(&routed, Some(&sync)).await;
+        assert!(problems.is_empty(), "{problems:?}");
+    }
+
+    #[test]
+    fn routes_misplaced_packages() {
+        let sync = SyncLookup {
+            found: HashSet::from(["git".to_string(), "base-devel".to_string()]),
+            names: Vec::new(),
+        };
+
+        let options = options("{ repo: [git, paru-bin], aur: [base-devel, yay-bin] }");
+        let routed = options.routed(&sync);
+
+        assert_eq!(routed.repo.unwrap(), ["git", "base-devel"]);
+        assert_eq!(routed.aur.unwrap(), ["yay-bin", "paru-bin"]);
+    }
+
+    fn local(name: &str, version: &str, provides: &[&str], replaces: &[&str]) -> LocalPackage {
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        LocalPackage {
+            package: PacmanPackage {
+                name: name.to_string(),
+                version: version.to_string(),
+                explicit: true,
+            },
+            provides: names(provides),
+            replaces: names(replaces),
+            depends: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn finds_replaced_packages_and_installed_groups() {
+        let packages = vec![
+            local("neovim-git", "0.11.0.r1-1", &["neovim", "vi"], &["neovim"]),
+            local("autoconf", "2.72-1", &[], &[]),
+            local("automake", "1.17-1", &[], &[]),
+            local("ttf-dejavu", "2.37-1", &[], &[]),
+        ];
+        let groups = [
+            (
+                "base-devel".to_string(),
+                vec!["autoconf".to_string(), "automake".to_string()],
+            ),
+            (
+                "fonts".to_string(),
+                vec!["ttf-dejavu".to_string(), "noto-fonts".to_string()],
+            ),
+        ];
+
+        let installed = index_installed(packages, &groups);
+
+        let neovim = &installed["neovim"];
+        assert_eq!(neovim.name, "neovim-git");
+        assert_eq!(installed["vi"].name, "neovim-git");
+        assert_eq!(installed["base-devel"].name, "autoconf");
+        assert!(!installed.contains_key("fonts"));
+
+        let (pacman, _) = pacman(Privileges::Root, false);
+        let options = options("{ repo: [neovim, base-devel, fonts] }");
+        let (missing, count) = pacman.filter_missing(installed, &options).unwrap();
+        assert_eq!(missing.repo.unwrap(), ["fonts"]);
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn bootstraps_missing_aur_helper() {
+        use utils::{AurHelper, select_aur_helper};
+
+        let runner = RecordingRunner::default();
+        for helper in ["paru", "yay", "pikaur", "trizen"] {
+            runner.script(helper, 127, "");
+        }
+
+        let selected = select_aur_helper(&runner, None, Some("paru-bin".to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            selected,
+            AurHelper::Bootstrap {
+                package: "paru-bin".to_string(),
+                helper: "paru".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn installs_package_files() {
+        let (pacman, runner) = pacman(Privileges::Root, false);
+
+        let options = options(
+            r#"
+            files:
+              - /home/user/dotfiles/tools-1.0-1-any.pkg.tar.zst
+              - file: /home/user/dotfiles/extras-2.0-1-any.pkg.tar.zst
+                sha256: 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+            "#,
+        );
+        pacman.install(options).await.unwrap();
+
+        assert_eq!(
+            runner.invocations(),
+            [invocation(
+                "pacman",
+                &[
+                    "-U",
+                    "--needed",
+                    "--noconfirm",
+                    "/home/user/dotfiles/tools-1.0-1-any.pkg.tar.zst",
+                    "/home/user/dotfiles/extras-2.0-1-any.pkg.tar.zst"
+                ],
+                false
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn verifies_pinned_package_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = "internal-tools-1.2.0-1-any.pkg.tar.zst";
+        files::write_package_file(&dir.path().join(file), "internal-tools", "1.2.0-1");
+        let content = std::fs::read(dir.path().join(file)).unwrap();
+
+        let (mut pacman, _) = pacman(Privileges::Root, false);
+        pacman.context.config_dir = dir.path().to_path_buf();
+        let pinned = |sha256: &str| {
+            options(&format!(
+                "{{ files: [{{ file: {file}, sha256: {sha256} }}] }}"
+            ))
+        };
+
+        pacman
+            .verify(&pinned(&verify::sha256(&content)))
+            .await
+            .unwrap();
+        let error = pacman
+            .verify(&pinned(&verify::sha256(b"tampered")))
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<VerificationFailed>().is_some());
+
+        // A misspelled pin must not install the file unverified
+        let misspelled = format!("{{ files: [{{ file: {file}, sha265: 3a5f }}] }}");
+        let error = serde_yaml::from_str::<PacmanOptions>(&misspelled).unwrap_err();
+        assert!(error.to_string().contains("`sha256`"), "{error}");
+    }
+
+    #[test]
+    fn installs_outdated_package_files_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("internal-tools-1.2.0-1-any.pkg.tar.zst");
+        files::write_package_file(&path, "internal-tools", "1.2.0-1");
+        let info = files::read_pkginfo(&path).unwrap();
+
+        // Stands in for libalpm's `vercmp`, which orders these versions the same way
+        fn vercmp(a: &str, b: &str) -> std::cmp::Ordering {
+            let parts = |version: &str| -> Vec<u32> {
+                version
+                    .split(['.', '-'])
+                    .map(|part| part.parse().unwrap())
+                    .collect()
+            };
+            parts(a).cmp(&parts(b))
+        }
+        let installed = |name: &str, version: &str| {
+            let package = PacmanPackage {
+                name: name.to_string(),
+                version: version.to_string(),
+                explicit: true,
+            };
+            HashMap::from([("internal-tools".to_string(), package)])
+        };
+
+        assert!(is_outdated(&HashMap::new(), &info, vercmp));
+        assert!(is_outdated(
+            &installed("internal-tools", "1.1.9-3"),
+            &info,
+            vercmp
+        ));
+        assert!(!is_outdated(
+            &installed("internal-tools", "1.2.0-1"),
+            &info,
+            vercmp
+        ));
+        assert!(!is_outdated(
+            &installed("internal-tools", "1.10.0-1"),
+            &info,
+            vercmp
+        ));
+
+        // Provided by another package, which doesn't make the file installed
+        let provided = installed("internal-tools-git", "1.3.0.r2-1");
+        assert!(is_outdated(&provided, &info, vercmp));
+    }
+
+    #[tokio::test]
+    async fn marks_dependencies_as_explicit() {
+        let (pacman, runner) = pacman(Privileges::Root, false);
+
+        let package = |name: &str, explicit| PacmanPackage {
+            name: name.to_string(),
+            version: "1.0-1".to_string(),
+            explicit,
+        };
+        let installed = HashMap::from([
+            ("git".to_string(), package("git", true)),
+            ("python".to_string(), package("python", false)),
+            // Provided by a dependency, which is left alone
+            ("rust".to_string(), package("rustup", false)),
+        ]);
+
+        let options = options("{ repo: [git, python, rust, zsh], explicit: true }");
+        let remarks = installed_as_dependencies(&installed, &options);
+        let (missing, count) = pacman.filter_missing(installed, &options).unwrap();
+
+        // Re-marked packages are neither missing nor installed by chezpilot
+        assert_eq!(remarks, ["python"]);
+        assert_eq!(missing.repo.as_deref().unwrap(), ["zsh"]);
+        assert_eq!(pacman.packages(&missing), ["zsh"]);
+        assert_eq!(count, 1);
+
+        pacman.remark(&options, &remarks).await.unwrap();
+        pacman.install(missing).await.unwrap();
+
+        assert_eq!(
+            runner.invocations(),
+            [
+                invocation("pacman", &["-D", "--asexplicit", "python"], false),
+                invocation("pacman", &["-S", "--needed", "--noconfirm", "zsh"], false),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rolls_back_logged_transaction_from_package_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("pkg");
+        std::fs::create_dir(&cache).unwrap();
+        for file in [
+            "zsh-5.9-4-x86_64.pkg.tar.zst",
+            "zsh-5.9-4-x86_64.pkg.tar.zst.sig",
+        ] {
+            std::fs::write(cache.join(file), "").unwrap();
+        }
+        let conf = dir.path().join("pacman.conf");
+        std::fs::write(
+            &conf,
+            format!("[options]\nCacheDir = {}\n", cache.display()),
+        )
+        .unwrap();
+
+        let (pacman, runner) = pacman(Privileges::Root, false);
+        let options = options(&format!(
+            "{{ config: {}, repo: [git, zsh] }}",
+            conf.display()
+        ));
+        let targets = options.repo.clone().unwrap();
+        let depending = |mut local: LocalPackage, depends: &[&str]| {
+            local.depends = depends.iter().map(|name| name.to_string()).collect();
+            local
+        };
+
+        // `htop` is installed by another lane meanwhile, and not part of the transaction
+        let before = vec![
+            local("zsh", "5.9-4", &[], &[]),
+            local("perl", "5.40.0-1", &[], &[]),
+        ];
+        let after = vec![
+            local("zsh", "5.9-5", &[], &[]),
+            local("perl", "5.40.0-1", &[], &[]),
+            depending(local("git", "2.47.0-1", &[], &[]), &["curl", "perl"]),
+            local("curl", "8.11.0-1", &[], &[]),
+            local("htop", "3.3.0-1", &[], &[]),
+        ];
+        let versions = |packages: &[LocalPackage]| {
+            packages
+                .iter()
+                .map(|local| (local.package.name.clone(), local.package.version.clone()))
+                .collect()
+        };
+        let affected = with_dependencies(&after, &[], &targets);
+        let mut changes = transactions::changes(&versions(&before), &versions(&after));
+        changes.retain(|change| affected.contains(change.package()));
+
+        let runs = dir.path().join("runs");
+        let mut run = RunLog::new();
+        run.transactions.push(Transaction {
+            groups: vec!["base".to_string()],
+            config: PackageManagerConfig::Pacman(options),
+            changes,
+            irreversible: Vec::new(),
+            rolled_back: false,
+        });
+        run.write(&runs.join(run.file_name())).await.unwrap();
+
+        let (_, run) = RunLog::latest(&runs).await.unwrap().unwrap();
+        let [transaction] = &run.transactions[..] else {
+            panic!("Expected a single transaction, got {:?}", run.transactions);
+        };
+        let PackageManagerConfig::Pacman(options) = &transaction.config else {
+            panic!("Expected a pacman transaction, got {}", transaction.config);
+        };
+        pacman
+            .rollback(options, &transaction.changes)
+            .await
+            .unwrap();
+
+        let conf = conf.display().to_string();
+        let file = cache.join("zsh-5.9-4-x86_64.pkg.tar.zst");
+        assert_eq!(
+            runner.invocations(),
+            [
+                invocation(
+                    "pacman",
+                    &["-R", "--noconfirm", "--config", &conf, "curl", "git"],
+                    false
+                ),
+                invocation(
+                    "pacman",
+                    &[
+                        "-U",
+                        "--noconfirm",
+                        "--config",
+                        &conf,
+                        &file.display().to_string()
+                    ],
+                    false
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn configures_repositories() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = dir.path().join("pacman.conf");
+        std::fs::write(
+            &conf,
+            "[options]\n\n[core]\nInclude = /etc/pacman.d/mirrorlist\n",
+        )
+        .unwrap();
+
+        let (pacman, runner) = pacman(Privileges::Root, false);
+        // The key is missing when planning and when preparing
+        runner.script("pacman-key", 1, "");
+        runner.script("pacman-key", 1, "");
+
+        let options: PacmanOptions = serde_yaml::from_str(&format!(
+            r#"
+            config: {}
+            repo: [chaotic-keyring]
+            repositories:
+              - name: chaotic-aur
+                servers: [https://cdn-mirror.chaotic.cx/$repo/$arch]
+                keys: [{{ id: 3056513887B78AEB }}]
+            "#,
+            conf.display()
+        ))
+        .unwrap();
+        // Batches of other groups share the drop-in, without declaring the same repositories twice
+        let other: PacmanOptions = serde_yaml::from_str(&format!(
+            r#"
+            config: {}
+            repositories:
+              - name: extras
+                servers: [https://example.com/$repo/$arch]
+            "#,
+            conf.display()
+        ))
+        .unwrap();
+
+        let drop_in = dir.path().join("pacman.d/chezpilot.conf");
+        let config = conf.display().to_string();
+        let steps = pacman.preparation(&[&options, &other]).await.unwrap();
+        assert_eq!(
+            steps,
+            [
+                "pacman-key --recv-keys 3056513887B78AEB".to_string(),
+                "pacman-key --lsign-key 3056513887B78AEB".to_string(),
+                format!("write {} with chaotic-aur, extras", drop_in.display()),
+                format!("include {} from {config}", drop_in.display()),
+                format!("pacman -Syu --noconfirm --config {config}"),
+            ]
+        );
+
+        pacman.prepare(&[&options, &other]).await.unwrap();
+        pacman.install(options).await.unwrap();
+
+        let list_keys = invocation("pacman-key", &["--list-keys", "3056513887B78AEB"], false);
+        assert_eq!(
+            runner.invocations(),
+            [
+                list_keys.clone(),
+                list_keys,
+                invocation("pacman-key", &["--recv-keys", "3056513887B78AEB"], false),
+                invocation("pacman-key", &["--lsign-key", "3056513887B78AEB"], false),
+                invocation(
+                    "pacman",
+                    &["-Syu", "--noconfirm", "--config", &config],
+                    false
+                ),
+                invocation(
+                    "pacman",
+                    &[
+                        "-S",
+                        "--needed",
+                        "--noconfirm",
+                        "--config",
+                        &config,
+                        "chaotic-keyring"
+                    ],
+                    false
+                ),
+            ]
+        );
+        assert_eq!(
+            std::fs::read_to_string(&drop_in).unwrap(),
+            "# Managed by chezpilot, manual changes will be overwritten\n\n\
+             [chaotic-aur]\nServer = https://cdn-mirror.chaotic.cx/$repo/$arch\n\n\
+             [extras]\nServer = https://example.com/$repo/$arch\n"
+        );
+        assert!(
+            std::fs::read_to_string(&conf)
+                .unwrap()
+                .ends_with(&format!("Include = {}\n", drop_in.display()))
+        );
+    }
+
+    #[tokio::test]
+    async fn installs_aur_packages_unattended() {
+        let (pacman, runner) = pacman(Privileges::Elevate(ElevationTool::Doas), false);
+
+        let options = options("{ aur: [paru-bin], force_aur_helper: paru }");
+        pacman.install(options).await.unwrap();
+
+        assert_eq!(
+            runner.invocations(),
+            [
+                invocation("paru", &["--version"], false),
+                invocation(
+                    "paru",
+                    &[
+                        "-S",
+                        "--needed",
+                        "--noconfirm",
+                        "--sudo",
+                        "doas",
+                        "paru-bin"
+                    ],
+                    false
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_elevation_as_root() {
+        let (pacman, runner) = pacman(Privileges::Root, false);
+
+        pacman.install(options("{ repo: [git] }")).await.unwrap();
+
+        assert_eq!(
+            runner.invocations(),
+            [invocation(
+                "pacman",
+                &["-S", "--needed", "--noconfirm", "git"],
+                false
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_when_pacman_fails() {
+        let (pacman, runner) = pacman(Privileges::Elevate(ElevationTool::Sudo), true);
+        runner.script("sudo", 1, "");
+
+        let options = options("{ repo: [git], aur: [paru-bin], force_aur_helper: paru }");
+        assert!(pacman.install(options).await.is_err());
+        assert_eq!(runner.invocations().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn reports_missing_forced_helper() {
+        let (pacman, runner) = pacman(Privileges::Root, true);
+        runner.script("yay", 127, "");
+
+        let options = options("{ aur: [paru-bin], force_aur_helper: yay }");
+        assert!(pacman.install(options).await.is_err());
+        assert_eq!(
+            runner.invocations(),
+            [invocation("yay", &["--version"], false)]
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::{HashMap, HashSet};
+
+use miette::{Context, IntoDiagnostic, Result};
+use serde::Deserialize;
+
+pub const DEFAULT_URL: &str = "https://aur.archlinux.org";
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    results: Vec<RpcPackage>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RpcPackage {
+    name: String,
+
+    /// Name of the git repository the package is built from, shared by split packages.
+    package_base: Option<String>,
+}
+
+/// Client of the AUR RPC interface and its git repositories.
+#[derive(Debug, Clone)]
+pub struct AurClient {
+    base_url: String,
+    git_url: String,
+    client: reqwest::Client,
+}
+
+impl AurClient {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        let base_url = base_url.into().trim_end_matches('/').to_string();
+        Self {
+            git_url: base_url.clone(),
+            base_url,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Clones package repositories from `git_url` instead of the RPC base URL.
+    pub fn with_git_url(mut self, git_url: impl Into<String>) -> Self {
+        self.git_url = git_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Uses the `AUR_URL` and `AUR_GIT_URL` environment variables,
+    /// so that a mirror or a local stand-in can be used.
+    pub fn from_env() -> Self {
+        let client =
+            Self::new(std::env::var("AUR_URL").unwrap_or_else(|_| DEFAULT_URL.to_string()));
+        match std::env::var("AUR_GIT_URL") {
+            Ok(git_url) => client.with_git_url(git_url),
+            Err(_) => client,
+        }
+    }
+
+    /// URL of the git repository of the package base `base`.
+    pub fn git_url(&self, base: &str) -> String {
+        format!("{}/{base}.git", self.git_url)
+    }
+
+    async fn query(&self, path: &str, query: &[(&str, &str)]) -> Result<Vec<RpcPackage>> {
+        let response: RpcResponse = self
+            .client
+            .get(format!("{}/rpc/v5/{path}", self.base_url))
+            .query(query)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .into_diagnostic()
+            .wrap_err("Failed to query the AUR")?
+            .json()
+            .await
+            .into_diagnostic()
+            .wrap_err("Failed to parse the AUR response")?;
+
+        Ok(response.results)
+    }
+
+    async fn info(&self, packages: &[String]) -> Result<Vec<RpcPackage>> {
+        let query: Vec<_> = packages
+            .iter()
+            .map(|package| ("arg[]", package.as_str()))
+            .collect();
+        self.query("info", &query).await
+    }
+
+    /// Names of `packages` that exist in the AUR.
+    pub async fn existing(&self, packages: &[String]) -> Result<HashSet<String>> {
+        let found = self.info(packages).await?;
+        Ok(found.into_iter().map(|package| package.name).collect())
+    }
+
+    /// Package bases of `packages` that exist in the AUR, keyed by package name.
+    ///
+    /// Split packages are built from the repository of their base, e.g. `python-foo` from `foo`.
+    pub async fn package_bases(&self, packages: &[String]) -> Result<HashMap<String, String>> {
+        let found = self.info(packages).await?;
+        Ok(found
+            .into_iter()
+            .map(|package| {
+                let base = package.package_base.unwrap_or_else(|| package.name.clone());
+                (package.name, base)
+            })
+            .collect())
+    }
+
+    /// Names of AUR packages containing `term`.
+    pub async fn search(&self, term: &str) -> Result<Vec<String>> {
+        let found = self
+            .query(&format!("search/{term}"), &[("by", "name")])
+            .await?;
+        Ok(found.into_iter().map(|package| package.name).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{method, path, query_param},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn finds_existing_packages() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/rpc/v5/info"))
+            .and(query_param("arg[]", "paru-bin"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "resultcount": 1,
+                "results": [{ "Name": "paru-bin", "Version": "2.0.4-1" }],
+            })))
+            .mount(&server)
+            .await;
+
+        let aur = AurClient::new(server.uri());
+        let existing = aur
+            .existing(&["paru-bin".to_string(), "paru-bim".to_string()])
+            .await
+            .unwrap();
+
+        assert_eq!(existing, HashSet::from(["paru-bin".to_string()]));
+    }
+}
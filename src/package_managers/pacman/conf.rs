@@ -0,0 +1,131 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use miette::Result;
+
+use crate::errors::UnableToReadPacmanConf;
+
+pub const DEFAULT_CONFIG: &str = "/etc/pacman.conf";
+pub const DEFAULT_CACHE_DIR: &str = "/var/cache/pacman/pkg";
+
+/// Settings read from `pacman.conf` that chezpilot needs to find the pacman databases and cache.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PacmanConf {
+    pub root_dir: Option<PathBuf>,
+    pub db_path: Option<PathBuf>,
+
+    /// Directories package files are cached in, the default one if empty.
+    pub cache_dirs: Vec<PathBuf>,
+
+    /// Names of configured repositories, in the order pacman searches them.
+    pub repositories: Vec<String>,
+}
+
+impl PacmanConf {
+    /// Reads `path`, or the default `pacman.conf` if `None`.
+    ///
+    /// A missing default config is treated as empty, a missing custom one is an error.
+    pub fn read(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path, true),
+            None => (Path::new(DEFAULT_CONFIG), false),
+        };
+
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Self::parse(&content, |path| fs::read_to_string(path).ok())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Ok(Self::default()),
+            Err(_) => Err(UnableToReadPacmanConf { path: path.into() }.into()),
+        }
+    }
+
+    /// Parses `content`, following `Include` directives with `include`.
+    ///
+    /// Included files may declare repositories of their own, like drop-ins written by chezpilot.
+    pub fn parse(content: &str, include: impl Fn(&Path) -> Option<String>) -> Self {
+        let mut conf = Self::default();
+        let mut section = String::new();
+        conf.parse_lines(content, &include, &mut section, 0);
+        conf
+    }
+
+    fn parse_lines(
+        &mut self,
+        content: &str,
+        include: &impl Fn(&Path) -> Option<String>,
+        section: &mut String,
+        depth: usize,
+    ) {
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                *section = name.to_string();
+                if name != "options" && !self.repositories.iter().any(|repo| repo == name) {
+                    self.repositories.push(name.to_string());
+                }
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = PathBuf::from(value.trim());
+
+            match (section.as_str(), key.trim()) {
+                ("options", "RootDir") => self.root_dir = Some(value),
+                ("options", "DBPath") => self.db_path = Some(value),
+                ("options", "CacheDir") => self.cache_dirs.push(value),
+                // Includes can't be nested endlessly, pacman gives up at a similar depth
+                (_, "Include") if depth < 10 => {
+                    if let Some(content) = include(&value) {
+                        self.parse_lines(&content, include, section, depth + 1);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_paths() {
+        let conf = PacmanConf::parse(
+            r#"
+            [options]
+            RootDir     = /mnt   # installation root
+            #DBPath     = /var/lib/pacman/
+            DBPath      = /mnt/var/lib/pacman/
+            CacheDir    = /mnt/var/cache/pacman/pkg/
+            CacheDir    = /srv/pacman/pkg/
+
+            [core]
+            DBPath = /ignored
+            Include = /etc/pacman.d/mirrorlist
+
+            [extra]
+            Include = /etc/pacman.d/chezpilot.conf
+            "#,
+            |path| {
+                (path == Path::new("/etc/pacman.d/chezpilot.conf"))
+                    .then(|| "[chaotic-aur]\nServer = https://example.org".to_string())
+            },
+        );
+
+        assert_eq!(conf.root_dir, Some(PathBuf::from("/mnt")));
+        assert_eq!(conf.db_path, Some(PathBuf::from("/mnt/var/lib/pacman/")));
+        assert_eq!(
+            conf.cache_dirs,
+            [
+                PathBuf::from("/mnt/var/cache/pacman/pkg/"),
+                PathBuf::from("/srv/pacman/pkg/")
+            ]
+        );
+        assert_eq!(conf.repositories, ["core", "extra", "chaotic-aur"]);
+    }
+}
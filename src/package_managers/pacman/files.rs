@@ -0,0 +1,217 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use miette::{IntoDiagnostic, Result};
+
+use crate::{errors::InvalidPackageFile, utils::cache_dir};
+
+/// Name and version of a package archive, read from its `.PKGINFO`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PkgInfo {
+    pub name: String,
+    pub version: String,
+}
+
+/// Resolves an entry of `files` to a local path.
+///
+/// Paths are relative to `config_dir`, URLs are downloaded into the cache once.
+pub async fn locate(entry: &str, config_dir: &Path) -> Result<PathBuf> {
+    if !is_url(entry) {
+        return Ok(config_dir.join(entry));
+    }
+
+    let Some(name) = entry.rsplit('/').next().filter(|name| !name.is_empty()) else {
+        miette::bail!("{entry} does not point to a package file");
+    };
+    let Some(dir) = downloads_dir() else {
+        miette::bail!("Unable to find a cache directory to download {entry} into");
+    };
+    let path = dir.join(name);
+
+    // Package file names contain their version, so a cached file never goes stale
+    if path.exists() {
+        return Ok(path);
+    }
+
+    let bytes = reqwest::get(entry)
+        .await
+        .and_then(|response| response.error_for_status())
+        .into_diagnostic()?
+        .bytes()
+        .await
+        .into_diagnostic()?;
+
+    // Written under a temporary name, so that an interrupted download is not mistaken for a package
+    tokio::fs::create_dir_all(&dir).await.into_diagnostic()?;
+    let partial = dir.join(format!("{name}.part"));
+    tokio::fs::write(&partial, bytes).await.into_diagnostic()?;
+    tokio::fs::rename(&partial, &path).await.into_diagnostic()?;
+
+    Ok(path)
+}
+
+/// Directory package files given as URLs are downloaded into.
+fn downloads_dir() -> Option<PathBuf> {
+    Some(cache_dir()?.join("packages"))
+}
+
+/// Whether `path` was downloaded into the cache by `locate`.
+pub fn is_downloaded(path: &Path) -> bool {
+    downloads_dir().is_some_and(|dir| path.starts_with(dir))
+}
+
+fn is_url(entry: &str) -> bool {
+    entry.starts_with("http://") || entry.starts_with("https://")
+}
+
+/// Reads `.PKGINFO` of a package archive compressed with zstd or xz.
+pub fn read_pkginfo(path: &Path) -> Result<PkgInfo> {
+    let invalid = |reason: &str| InvalidPackageFile {
+        path: path.to_path_buf(),
+        reason: reason.to_string(),
+    };
+
+    let mut file = BufReader::new(File::open(path).map_err(|e| invalid(&e.to_string()))?);
+    let mut magic = [0; 6];
+    file.read_exact(&mut magic)
+        .map_err(|e| invalid(&e.to_string()))?;
+    let file = std::io::Cursor::new(magic).chain(file);
+
+    let decoder: Box<dyn Read> = match magic {
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => {
+            Box::new(zstd::Decoder::new(file).map_err(|e| invalid(&e.to_string()))?)
+        }
+        [0xfd, b'7', b'z', b'X', b'Z', 0x00] => Box::new(xz2::read::XzDecoder::new(file)),
+        _ => return Err(invalid("unsupported compression").into()),
+    };
+
+    let mut archive = tar::Archive::new(decoder);
+    for entry in archive.entries().map_err(|e| invalid(&e.to_string()))? {
+        let mut entry = entry.map_err(|e| invalid(&e.to_string()))?;
+        if entry.path().is_ok_and(|path| path == Path::new(".PKGINFO")) {
+            let mut content = String::new();
+            entry
+                .read_to_string(&mut content)
+                .map_err(|e| invalid(&e.to_string()))?;
+            return parse_pkginfo(&content).ok_or_else(|| invalid("incomplete .PKGINFO").into());
+        }
+    }
+
+    Err(invalid("no .PKGINFO found").into())
+}
+
+/// Finds the package file of `name` at `version` in the package cache.
+pub fn find_cached(cache_dirs: &[PathBuf], name: &str, version: &str) -> Option<PathBuf> {
+    cache_dirs
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.file_name()
+                .and_then(|file_name| file_name.to_str())
+                .and_then(parse_file_name)
+                .is_some_and(|info| info.name == name && info.version == version)
+        })
+}
+
+/// Reads name and version from a package file name like `zsh-5.9-5-x86_64.pkg.tar.zst`.
+///
+/// Signatures and partial downloads next to package files are skipped.
+fn parse_file_name(file_name: &str) -> Option<PkgInfo> {
+    let (stem, extension) = file_name.split_once(".pkg.tar")?;
+    if !extension.is_empty() && ![".zst", ".xz", ".gz", ".bz2"].contains(&extension) {
+        return None;
+    }
+
+    // Names may contain dashes, versions and architectures can't
+    let mut parts = stem.rsplitn(4, '-');
+    let _architecture = parts.next()?;
+    let release = parts.next()?;
+    let version = parts.next()?;
+    let name = parts.next()?;
+
+    Some(PkgInfo {
+        name: name.to_string(),
+        version: format!("{version}-{release}"),
+    })
+}
+
+fn parse_pkginfo(content: &str) -> Option<PkgInfo> {
+    let mut name = None;
+    let mut version = None;
+
+    for line in content.lines() {
+        match line.split_once(" = ") {
+            Some(("pkgname", value)) => name = Some(value.trim().to_string()),
+            Some(("pkgver", value)) => version = Some(value.trim().to_string()),
+            _ => {}
+        }
+    }
+
+    Some(PkgInfo {
+        name: name?,
+        version: version?,
+    })
+}
+
+/// Writes a zstd compressed package archive containing only a `.PKGINFO`.
+#[cfg(test)]
+pub fn write_package_file(path: &Path, name: &str, version: &str) {
+    let pkginfo = format!("# Generated by makepkg\npkgname = {name}\npkgver = {version}\n");
+    let mut header = tar::Header::new_gnu();
+    header.set_size(pkginfo.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+
+    let encoder = zstd::Encoder::new(File::create(path).unwrap(), 0).unwrap();
+    let mut builder = tar::Builder::new(encoder.auto_finish());
+    builder
+        .append_data(&mut header, ".PKGINFO", pkginfo.as_bytes())
+        .unwrap();
+    builder.into_inner().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_package_file_names() {
+        let info = |name: &str, version: &str| PkgInfo {
+            name: name.to_string(),
+            version: version.to_string(),
+        };
+
+        assert_eq!(
+            parse_file_name("visual-studio-code-bin-1.95.3-1-x86_64.pkg.tar.zst"),
+            Some(info("visual-studio-code-bin", "1.95.3-1"))
+        );
+        assert_eq!(
+            parse_file_name("python-1:3.12.7-1-x86_64.pkg.tar.xz"),
+            Some(info("python", "1:3.12.7-1"))
+        );
+        assert_eq!(parse_file_name("zsh-5.9-5-x86_64.pkg.tar.zst.sig"), None);
+        assert_eq!(parse_file_name("zsh-5.9-5-x86_64.pkg.tar.zst.part"), None);
+    }
+
+    #[test]
+    fn reads_pkginfo_from_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("internal-tools-1.2.0-1-any.pkg.tar.zst");
+
+        write_package_file(&path, "internal-tools", "1.2.0-1");
+
+        assert_eq!(
+            read_pkginfo(&path).unwrap(),
+            PkgInfo {
+                name: "internal-tools".to_string(),
+                version: "1.2.0-1".to_string(),
+            }
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    path::PathBuf,
+};
+
+use miette::{IntoDiagnostic, Result};
+use owo_colors::OwoColorize;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::info;
+
+use crate::{
+    errors::Cancelled,
+    runner::{CommandRunner, Invocation},
+    utils::cache_dir,
+};
+
+use super::aur::AurClient;
+
+/// Default directory for package repositories, `$XDG_CACHE_HOME/chezpilot/aur`.
+pub fn default_build_dir() -> Option<PathBuf> {
+    Some(cache_dir()?.join("aur"))
+}
+
+/// Builds AUR packages with `makepkg`, used when no AUR helper is installed.
+pub struct Makepkg<'a> {
+    pub runner: &'a dyn CommandRunner,
+    pub aur: &'a AurClient,
+
+    /// Directory package repositories are cloned into.
+    pub build_dir: PathBuf,
+    pub interactive: bool,
+
+    /// Whether to show PKGBUILDs before building them.
+    pub review: bool,
+}
+
+impl Makepkg<'_> {
+    /// Builds and installs `packages` along with their AUR-only dependencies.
+    ///
+    /// `available` returns which of the given dependencies can be installed without the AUR.
+    pub async fn install(
+        &self,
+        packages: &[String],
+        available: impl Fn(&[String]) -> Result<HashSet<String>>,
+    ) -> Result<()> {
+        let (bases, dependencies) = self.resolve(packages, available).await?;
+        let declared: Vec<String> = packages
+            .iter()
+            .filter_map(|package| bases.get(package))
+            .cloned()
+            .collect();
+        let order = build_order(&declared, &dependencies);
+
+        // Everything is reviewed up front, so that a rejected PKGBUILD doesn't leave a partial install
+        if self.review {
+            for base in &order {
+                self.review(base).await?;
+            }
+        }
+
+        for base in order {
+            let mut args = vec!["-si", "--needed"];
+            if !self.interactive {
+                args.push("--noconfirm");
+            }
+            if !declared.contains(&base) {
+                args.push("--asdeps");
+            }
+
+            info!("Building {}", base.blue().bold());
+            let invocation = Invocation::new("makepkg")
+                .args(args)
+                .stdin(self.interactive)
+                .current_dir(self.build_dir.join(&base));
+            self.run(&invocation).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Fetches the package bases of `packages` and of their dependencies that are not `available`.
+    ///
+    /// Returns the base of every fetched package, keyed by package name,
+    /// and the bases of AUR dependencies of every fetched base.
+    async fn resolve(
+        &self,
+        packages: &[String],
+        available: impl Fn(&[String]) -> Result<HashSet<String>>,
+    ) -> Result<(HashMap<String, String>, HashMap<String, Vec<String>>)> {
+        let mut bases = HashMap::new();
+        let mut dependencies: HashMap<String, Vec<String>> = HashMap::new();
+        let mut queue = packages.to_vec();
+
+        while !queue.is_empty() {
+            let found_bases = self.aur.package_bases(&queue).await?;
+            let mut found = Vec::new();
+            for package in queue {
+                let Some(base) = found_bases.get(&package) else {
+                    miette::bail!("{package} was not found in the AUR");
+                };
+                bases.insert(package, base.clone());
+
+                // Split packages share the repository of their base
+                if !dependencies.contains_key(base) {
+                    let depends = self.fetch(base).await?;
+                    found.extend(depends.iter().cloned());
+                    dependencies.insert(base.clone(), depends);
+                }
+            }
+
+            found.sort();
+            found.dedup();
+            found.retain(|dependency| !bases.contains_key(dependency));
+
+            let available = available(&found)?;
+            queue = found
+                .into_iter()
+                .filter(|dependency| !available.contains(dependency))
+                .collect();
+        }
+
+        // Only dependencies that were fetched come from the AUR
+        for depends in dependencies.values_mut() {
+            let mut fetched: Vec<String> = depends
+                .iter()
+                .filter_map(|dependency| bases.get(dependency))
+                .cloned()
+                .collect();
+            fetched.dedup();
+            *depends = fetched;
+        }
+
+        Ok((bases, dependencies))
+    }
+
+    /// Clones or updates the repository of the package base `base` and returns its dependencies.
+    async fn fetch(&self, base: &str) -> Result<Vec<String>> {
+        let dir = self.build_dir.join(base);
+
+        let invocation = if dir.join(".git").exists() {
+            Invocation::new("git")
+                .arg("-C")
+                .arg(dir.display().to_string())
+                .args(["pull", "--ff-only"])
+        } else {
+            tokio::fs::create_dir_all(&self.build_dir)
+                .await
+                .into_diagnostic()?;
+            Invocation::new("git")
+                .args(["clone", "--depth", "1"])
+                .arg(self.aur.git_url(base))
+                .arg(dir.display().to_string())
+        };
+        self.run(&invocation).await?;
+
+        let Ok(srcinfo) = tokio::fs::read_to_string(dir.join(".SRCINFO")).await else {
+            miette::bail!("{base} has no .SRCINFO, make sure it is an AUR package");
+        };
+        Ok(parse_dependencies(&srcinfo))
+    }
+
+    async fn review(&self, base: &str) -> Result<()> {
+        let pkgbuild = self.build_dir.join(base).join("PKGBUILD");
+        let content = tokio::fs::read_to_string(&pkgbuild)
+            .await
+            .into_diagnostic()?;
+
+        println!("\n{}", format!("{base}/PKGBUILD").bold());
+        println!("{}", content.dimmed());
+
+        if !self.interactive {
+            return Ok(());
+        }
+
+        print!("{} ", format!("Build {base}? [y/N]").bold());
+        std::io::stdout().flush().into_diagnostic()?;
+        let answer = BufReader::new(tokio::io::stdin())
+            .lines()
+            .next_line()
+            .await
+            .into_diagnostic()?
+            .unwrap_or_default();
+
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => Ok(()),
+            _ => Err(Cancelled.into()),
+        }
+    }
+
+    async fn run(&self, invocation: &Invocation) -> Result<()> {
+        let status = self.runner.status(invocation).await.into_diagnostic()?;
+
+        if !status.success() {
+            miette::bail!("{} exited with status: {}", invocation.program, status);
+        }
+        Ok(())
+    }
+}
+
+/// Dependencies needed to build and install a package, read from its `.SRCINFO`.
+///
+/// Version constraints are dropped, e.g. `python>=3.12` becomes `python`.
+fn parse_dependencies(srcinfo: &str) -> Vec<String> {
+    let mut dependencies: Vec<String> = Vec::new();
+
+    for line in srcinfo.lines() {
+        let Some((key, value)) = line.trim().split_once(" = ") else {
+            continue;
+        };
+
+        // Architecture specific dependencies look like `depends_x86_64`
+        let key = key.split('_').next().unwrap_or_default();
+        if !matches!(key, "depends" | "makedepends" | "checkdepends") {
+            continue;
+        }
+
+        let name = value
+            .split(['<', '>', '='])
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        if !name.is_empty() && !dependencies.contains(&name) {
+            dependencies.push(name);
+        }
+    }
+
+    dependencies
+}
+
+/// Orders packages so that every package is built after its dependencies.
+fn build_order(packages: &[String], dependencies: &HashMap<String, Vec<String>>) -> Vec<String> {
+    fn visit(
+        package: &str,
+        dependencies: &HashMap<String, Vec<String>>,
+        visited: &mut HashSet<String>,
+        order: &mut Vec<String>,
+    ) {
+        if !visited.insert(package.to_string()) {
+            return;
+        }
+        for dependency in dependencies.get(package).into_iter().flatten() {
+            visit(dependency, dependencies, visited, order);
+        }
+        order.push(package.to_string());
+    }
+
+    let mut visited = HashSet::new();
+    let mut order = Vec::new();
+    for package in packages {
+        visit(package, dependencies, &mut visited, &mut order);
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::runner::RecordingRunner;
+
+    #[test]
+    fn parse_srcinfo_dependencies() {
+        let dependencies = parse_dependencies(
+            r#"
+pkgbase = foo
+	pkgver = 1.0
+	makedepends = cargo
+	depends = glibc
+	depends = python>=3.12
+	depends_x86_64 = lib32-glibc
+
+pkgname = foo
+	depends = glibc
+"#,
+        );
+
+        assert_eq!(dependencies, ["cargo", "glibc", "python", "lib32-glibc"]);
+    }
+
+    #[tokio::test]
+    async fn builds_aur_dependencies_first() {
+        use wiremock::{
+            Mock, MockServer, ResponseTemplate,
+            matchers::{path, query_param},
+        };
+
+        // `bar` is a split package, built from the repository of `bar-split`
+        let server = MockServer::start().await;
+        for (name, base) in [("foo", "foo"), ("bar", "bar-split")] {
+            Mock::given(path("/rpc/v5/info"))
+                .and(query_param("arg[]", name))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "results": [{ "Name": name, "PackageBase": base }],
+                })))
+                .mount(&server)
+                .await;
+        }
+
+        let build_dir = tempfile::tempdir().unwrap();
+        for (base, srcinfo) in [
+            ("foo", "pkgbase = foo\n\tdepends = bar\n\tmakedepends = git"),
+            ("bar-split", "pkgbase = bar-split\n\tdepends = glibc"),
+        ] {
+            let dir = build_dir.path().join(base);
+            fs::create_dir_all(dir.join(".git")).unwrap();
+            fs::write(dir.join(".SRCINFO"), srcinfo).unwrap();
+        }
+
+        let runner = RecordingRunner::default();
+        let aur = AurClient::new(server.uri());
+        let makepkg = Makepkg {
+            runner: &runner,
+            aur: &aur,
+            build_dir: build_dir.path().to_path_buf(),
+            interactive: false,
+            review: false,
+        };
+
+        let available = |names: &[String]| {
+            Ok(names
+                .iter()
+                .filter(|name| matches!(name.as_str(), "git" | "glibc"))
+                .cloned()
+                .collect())
+        };
+        makepkg
+            .install(&["foo".to_string()], available)
+            .await
+            .unwrap();
+
+        let dir = |base: &str| build_dir.path().join(base);
+        let pull = |base: &str| {
+            Invocation::new("git")
+                .arg("-C")
+                .arg(dir(base).display().to_string())
+                .args(["pull", "--ff-only"])
+        };
+        assert_eq!(
+            runner.invocations(),
+            [
+                pull("foo"),
+                pull("bar-split"),
+                Invocation::new("makepkg")
+                    .args(["-si", "--needed", "--noconfirm", "--asdeps"])
+                    .current_dir(dir("bar-split")),
+                Invocation::new("makepkg")
+                    .args(["-si", "--needed", "--noconfirm"])
+                    .current_dir(dir("foo")),
+            ]
+        );
+    }
+}
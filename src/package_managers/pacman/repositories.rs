@@ -0,0 +1,284 @@
+use std::path::{Path, PathBuf};
+
+use miette::{IntoDiagnostic, Result};
+use owo_colors::OwoColorize;
+use tracing::info;
+
+use super::{Pacman, PacmanKey, PacmanOptions, PacmanRepository, conf::DEFAULT_CONFIG};
+use crate::{
+    errors::{ConflictingPacmanRepository, UnableToReadPacmanConf},
+    runner::Invocation,
+};
+
+/// Drop-in holding managed repositories, included from `conf`.
+pub fn drop_in_path(conf: &Path) -> PathBuf {
+    conf.parent()
+        .unwrap_or(Path::new("/"))
+        .join("pacman.d")
+        .join("chezpilot.conf")
+}
+
+fn render(repositories: &[&PacmanRepository]) -> String {
+    let mut content = "# Managed by chezpilot, manual changes will be overwritten\n".to_string();
+    for repository in repositories {
+        content.push_str(&format!("\n[{}]\n", repository.name));
+        if let Some(sig_level) = &repository.sig_level {
+            content.push_str(&format!("SigLevel = {sig_level}\n"));
+        }
+        for server in &repository.servers {
+            content.push_str(&format!("Server = {server}\n"));
+        }
+    }
+    content
+}
+
+/// Repositories declared by every batch installing into the same installation.
+struct Setup<'a> {
+    /// Options of the first batch, used to point `pacman` at the installation.
+    options: &'a PacmanOptions,
+    repositories: Vec<&'a PacmanRepository>,
+}
+
+impl Setup<'_> {
+    fn conf_path(&self) -> PathBuf {
+        self.options
+            .config
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG))
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &PacmanKey> {
+        self.repositories
+            .iter()
+            .flat_map(|repository| repository.keys.iter().flatten())
+    }
+}
+
+/// Groups repositories of all `options` by installation, so that the drop-in of each
+/// holds the repositories of every batch instead of only the last one installed.
+fn setups<'a>(options: &[&'a PacmanOptions]) -> Result<Vec<Setup<'a>>> {
+    let mut setups: Vec<Setup> = Vec::new();
+
+    for options in options {
+        let Some(repositories) = options.repositories.as_ref().filter(|r| !r.is_empty()) else {
+            continue;
+        };
+
+        let index = match setups
+            .iter()
+            .position(|setup| setup.options.same_location(options))
+        {
+            Some(index) => index,
+            None => {
+                setups.push(Setup {
+                    options,
+                    repositories: Vec::new(),
+                });
+                setups.len() - 1
+            }
+        };
+
+        let setup = &mut setups[index];
+        for repository in repositories {
+            match setup
+                .repositories
+                .iter()
+                .find(|r| r.name == repository.name)
+            {
+                Some(existing) if *existing != repository => {
+                    let name = repository.name.clone();
+                    return Err(ConflictingPacmanRepository { name }.into());
+                }
+                Some(_) => {}
+                None => setup.repositories.push(repository),
+            }
+        }
+    }
+
+    Ok(setups)
+}
+
+/// Files that have to change for the repositories of a setup to be added.
+struct Edits {
+    drop_in: PathBuf,
+
+    /// New content of the drop-in, if it differs.
+    content: Option<String>,
+
+    conf_path: PathBuf,
+
+    /// New content of `pacman.conf`, if it doesn't include the drop-in yet.
+    conf: Option<String>,
+}
+
+impl Edits {
+    async fn read(setup: &Setup<'_>) -> Result<Self> {
+        let conf_path = setup.conf_path();
+        let drop_in = drop_in_path(&conf_path);
+
+        let content = render(&setup.repositories);
+        let current = tokio::fs::read_to_string(&drop_in).await.ok();
+        let content = (current.as_deref() != Some(content.as_str())).then_some(content);
+
+        let conf =
+            tokio::fs::read_to_string(&conf_path)
+                .await
+                .map_err(|_| UnableToReadPacmanConf {
+                    path: conf_path.clone(),
+                })?;
+        let include = format!("Include = {}", drop_in.display());
+        let conf = (!conf.lines().any(|line| line.trim() == include)).then(|| {
+            let separator = if conf.is_empty() || conf.ends_with('\n') {
+                ""
+            } else {
+                "\n"
+            };
+            format!("{conf}{separator}\n# Repositories managed by chezpilot\n{include}\n")
+        });
+
+        Ok(Self {
+            drop_in,
+            content,
+            conf_path,
+            conf,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.content.is_none() && self.conf.is_none()
+    }
+}
+
+impl Pacman {
+    /// Steps `add_repositories` takes for the repositories of `options`, shown in the plan.
+    pub(super) async fn repository_steps(&self, options: &[&PacmanOptions]) -> Result<Vec<String>> {
+        let mut steps = Vec::new();
+
+        for setup in setups(options)? {
+            for key in self.missing_keys(&setup).await? {
+                for invocation in self.key_invocations(key, Invocation::new("pacman-key")) {
+                    steps.push(invocation.to_string());
+                }
+            }
+
+            let edits = Edits::read(&setup).await?;
+            if edits.content.is_some() {
+                let names: Vec<&str> = setup.repositories.iter().map(|r| r.name.as_str()).collect();
+                steps.push(format!(
+                    "write {} with {}",
+                    edits.drop_in.display(),
+                    names.join(", ")
+                ));
+            }
+            if edits.conf.is_some() {
+                steps.push(format!(
+                    "include {} from {}",
+                    edits.drop_in.display(),
+                    edits.conf_path.display()
+                ));
+            }
+            if !edits.is_empty() {
+                let invocation = self.sync_invocation(Invocation::new("pacman"), setup.options);
+                steps.push(invocation.to_string());
+            }
+        }
+
+        Ok(steps)
+    }
+
+    /// Imports keys of the repositories declared by all `options` and writes them to the drop-in,
+    /// upgrading the system if anything changed.
+    ///
+    /// Runs once before anything is installed, as batches of different groups share the drop-in.
+    pub(super) async fn add_repositories(&self, options: &[&PacmanOptions]) -> Result<()> {
+        for setup in setups(options)? {
+            // Keys come first, so that the sync can verify signed databases
+            for key in self.missing_keys(&setup).await? {
+                info!("Importing key {}", key.id.blue().bold());
+                for invocation in self.key_invocations(key, self.context.elevated("pacman-key")?) {
+                    self.run(&invocation).await?;
+                }
+            }
+
+            let edits = Edits::read(&setup).await?;
+            if let Some(content) = &edits.content {
+                info!("Writing {}", edits.drop_in.display().blue().bold());
+                self.context.write_file(&edits.drop_in, content).await?;
+            }
+            if let Some(conf) = &edits.conf {
+                info!(
+                    "Including it from {}",
+                    edits.conf_path.display().blue().bold()
+                );
+                self.context.write_file(&edits.conf_path, conf).await?;
+            }
+
+            if !edits.is_empty() {
+                info!("Syncing the databases and upgrading the system");
+                let invocation =
+                    self.sync_invocation(self.context.elevated("pacman")?, setup.options);
+                self.run(&invocation).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Syncs the databases along with a full upgrade, as installing packages from freshly
+    /// synced databases without upgrading the rest of the system is a partial upgrade.
+    fn sync_invocation(&self, pacman: Invocation, options: &PacmanOptions) -> Invocation {
+        let mut invocation = pacman.arg("-Syu");
+        if !self.context.interactive {
+            invocation = invocation.arg("--noconfirm");
+        }
+        invocation.args(options.location_args())
+    }
+
+    /// Keys of a setup that are not in the pacman keyring yet.
+    async fn missing_keys<'a>(&self, setup: &'a Setup<'_>) -> Result<Vec<&'a PacmanKey>> {
+        let mut missing = Vec::new();
+        for key in setup.keys() {
+            // Listing keys doesn't need root, unlike changing the keyring
+            let listed = self
+                .context
+                .runner
+                .output(
+                    &Invocation::new("pacman-key")
+                        .arg("--list-keys")
+                        .arg(&key.id),
+                )
+                .await
+                .into_diagnostic()?;
+            if !listed.status.success() && !missing.contains(&key) {
+                missing.push(key);
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Invocations of `pacman_key` importing `key` and signing it locally.
+    fn key_invocations(&self, key: &PacmanKey, pacman_key: Invocation) -> [Invocation; 2] {
+        let import = match &key.file {
+            Some(file) => pacman_key
+                .clone()
+                .arg("--add")
+                .arg(self.context.config_dir.join(file).display().to_string()),
+            None => pacman_key.clone().arg("--recv-keys").arg(&key.id),
+        };
+        [import, pacman_key.arg("--lsign-key").arg(&key.id)]
+    }
+
+    pub(super) async fn run(&self, invocation: &Invocation) -> Result<()> {
+        let status = self
+            .context
+            .runner
+            .status(invocation)
+            .await
+            .into_diagnostic()?;
+
+        if !status.success() {
+            miette::bail!("`{invocation}` exited with status: {status}");
+        }
+        Ok(())
+    }
+}
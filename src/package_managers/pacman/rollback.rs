@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+
+use miette::Result;
+use owo_colors::OwoColorize;
+use tracing::info;
+
+use super::{Pacman, PacmanOptions, conf, files};
+use crate::{errors::CachedPackageNotFound, runner::Invocation, transactions::Change};
+
+impl Pacman {
+    /// Removes packages installed by `changes` and downgrades upgraded ones from the package cache.
+    pub(super) async fn revert(&self, options: &PacmanOptions, changes: &[Change]) -> Result<()> {
+        let mut installed = Vec::new();
+        let mut upgraded = Vec::new();
+        for change in changes {
+            match change {
+                Change::Installed { package, .. } => installed.push(package.clone()),
+                Change::Upgraded { package, from, .. } => upgraded.push((package, from)),
+            }
+        }
+
+        // Package files are looked up first, so that nothing is removed if a downgrade is impossible
+        let conf = conf::PacmanConf::read(options.config.as_deref())?;
+        let cache_dirs = if conf.cache_dirs.is_empty() {
+            vec![PathBuf::from(conf::DEFAULT_CACHE_DIR)]
+        } else {
+            conf.cache_dirs
+        };
+        let cached = upgraded
+            .iter()
+            .map(|(package, version)| {
+                files::find_cached(&cache_dirs, package, version).ok_or_else(|| {
+                    CachedPackageNotFound {
+                        name: package.to_string(),
+                        version: version.to_string(),
+                    }
+                    .into()
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if !installed.is_empty() {
+            info!("Removing {} packages", installed.len().blue().bold());
+            self.run(&self.rollback_invocation("-R", options, installed)?)
+                .await?;
+        }
+
+        if !cached.is_empty() {
+            info!("Downgrading {} packages", cached.len().blue().bold());
+            let files = cached.iter().map(|path| path.display().to_string());
+            self.run(&self.rollback_invocation("-U", options, files)?)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    fn rollback_invocation(
+        &self,
+        operation: &str,
+        options: &PacmanOptions,
+        targets: impl IntoIterator<Item = String>,
+    ) -> Result<Invocation> {
+        let mut invocation = self.context.elevated("pacman")?.arg(operation);
+        if !self.context.interactive {
+            invocation = invocation.arg("--noconfirm");
+        }
+        Ok(invocation.args(options.location_args()).args(targets))
+    }
+}
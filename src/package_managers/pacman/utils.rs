@@ -1,9 +1,11 @@
 use owo_colors::OwoColorize;
 use thiserror::Error;
-use tokio::process::Command;
 use tracing::{info, instrument};
 
-use crate::errors::{AurHelperNotDetected, AurHelperPinReason, RequestedAurHelperNotFound};
+use crate::{
+    errors::{AurHelperNotDetected, AurHelperPinReason, RequestedAurHelperNotFound},
+    runner::{CommandRunner, Invocation},
+};
 
 static AUR_HELPERS: &[&str] = &["paru", "yay", "pikaur", "trizen"];
 
@@ -14,14 +16,26 @@ pub enum AurDetectionError {
     RequestedAurHelperNotFound(#[from] RequestedAurHelperNotFound),
 }
 
+/// AUR helper chosen to install AUR packages.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AurHelper {
+    /// A helper that is already installed.
+    Installed(String),
+
+    /// No helper is installed, `package` has to be built from the AUR first to provide `helper`.
+    Bootstrap { package: String, helper: String },
+}
+
 async fn check_helper(
+    runner: &dyn CommandRunner,
     helper: &str,
     reason: AurHelperPinReason,
 ) -> Result<(), RequestedAurHelperNotFound> {
-    let result = Command::new(helper).arg("--version").status().await;
+    let invocation = Invocation::new(helper).arg("--version");
+    let result = runner.output(&invocation).await;
 
-    if let Ok(status) = result
-        && status.success()
+    if let Ok(output) = result
+        && output.status.success()
     {
         return Ok(());
     }
@@ -32,14 +46,16 @@ async fn check_helper(
     })
 }
 
-#[instrument(skip(force_aur_helper))]
+#[instrument(skip(runner, force_aur_helper, bootstrap_aur_helper))]
 pub async fn select_aur_helper(
+    runner: &dyn CommandRunner,
     force_aur_helper: Option<String>,
-) -> Result<String, AurDetectionError> {
+    bootstrap_aur_helper: Option<String>,
+) -> Result<AurHelper, AurDetectionError> {
     // First, we check for config overides
     if let Some(helper) = force_aur_helper {
-        check_helper(&helper, AurHelperPinReason::ConfigOverride).await?;
-        return Ok(helper);
+        check_helper(runner, &helper, AurHelperPinReason::ConfigOverride).await?;
+        return Ok(AurHelper::Installed(helper));
     }
 
     // Then we check for env variable overrides
@@ -48,13 +64,13 @@ pub async fn select_aur_helper(
             "Using {env_override} AUR helper {}",
             "(env override)".bold().dimmed()
         );
-        check_helper(&env_override, AurHelperPinReason::ConfigOverride).await?;
-        return Ok(env_override);
+        check_helper(runner, &env_override, AurHelperPinReason::ConfigOverride).await?;
+        return Ok(AurHelper::Installed(env_override));
     }
 
     // Then we try the most popular AUR helpers
     for helper in AUR_HELPERS {
-        if check_helper(helper, AurHelperPinReason::Other)
+        if check_helper(runner, helper, AurHelperPinReason::Other)
             .await
             .is_ok()
         {
@@ -62,10 +78,25 @@ pub async fn select_aur_helper(
                 "Using {helper} AUR helper {}",
                 "(autodetect)".bold().dimmed()
             );
-            return Ok(helper.to_string());
+            return Ok(AurHelper::Installed(helper.to_string()));
         }
     }
 
+    // Finally, we fall back to building the configured helper
+    if let Some(package) = bootstrap_aur_helper {
+        // Packages like `paru-bin` and `yay-git` provide the plain command
+        let helper = package
+            .strip_suffix("-bin")
+            .or_else(|| package.strip_suffix("-git"))
+            .unwrap_or(&package)
+            .to_string();
+        info!(
+            "Using {helper} AUR helper {}",
+            "(bootstrap)".bold().dimmed()
+        );
+        return Ok(AurHelper::Bootstrap { package, helper });
+    }
+
     Err(AurDetectionError::AurHelperNotDetected(
         AurHelperNotDetected,
     ))
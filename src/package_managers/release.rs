@@ -0,0 +1,846 @@
+mod api;
+mod archive;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use globset::Glob;
+use miette::{IntoDiagnostic, Result};
+use owo_colors::OwoColorize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    config::OsName,
+    errors::{AmbiguousReleaseAsset, ReleaseAssetNotFound, VerificationFailed},
+    filter::get_system_info,
+    package_managers::{Context, PackageManager},
+    transactions::Change,
+    utils::{cache_dir, state_dir, write_atomically},
+    verify,
+};
+
+pub use api::Forge;
+use api::{ReleaseClient, Selector, tag_version};
+
+/// Installs static binaries from GitHub or Gitea releases.
+pub struct Release {
+    context: Context,
+
+    /// Values of the `{os}` and `{arch}` placeholders of asset patterns.
+    os: &'static str,
+    arch: &'static str,
+
+    /// File recording installed releases, `$XDG_STATE_HOME/chezpilot/releases.json`.
+    installed_path: Option<PathBuf>,
+
+    /// Directory downloaded assets are cached in, `$XDG_CACHE_HOME/chezpilot/releases`.
+    cache_dir: Option<PathBuf>,
+}
+
+/// A release installed by chezpilot.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+pub struct InstalledRelease {
+    /// Repository the release was downloaded from.
+    pub repo: String,
+
+    pub tag: String,
+
+    /// Path the binary was installed to.
+    pub path: PathBuf,
+}
+
+impl Release {
+    pub fn new(context: Context) -> Result<Self> {
+        let system = get_system_info()?;
+        // Names most release assets use, e.g. `ripgrep-14.1.1-x86_64-unknown-linux-musl.tar.gz`
+        let os = match system.os {
+            OsName::Linux => "linux",
+            OsName::MacOS => "darwin",
+            OsName::Windows => "windows",
+        };
+
+        Ok(Self {
+            context,
+            os,
+            arch: system.arch,
+            installed_path: state_dir().map(|dir| dir.join("releases.json")),
+            cache_dir: cache_dir().map(|dir| dir.join("releases")),
+        })
+    }
+
+    /// Releases installed by chezpilot, keyed by the path the binary was installed to,
+    /// so that binaries installed from the same repository are recorded separately.
+    async fn read_installed(&self) -> Result<BTreeMap<String, InstalledRelease>> {
+        let Some(path) = &self.installed_path else {
+            return Ok(BTreeMap::new());
+        };
+        match tokio::fs::read_to_string(path).await {
+            Ok(content) => serde_json::from_str(&content).into_diagnostic(),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(error) => Err(error).into_diagnostic(),
+        }
+    }
+
+    /// Records `release` as installed to `binary`, or forgets about `binary` if it is `None`.
+    async fn write_installed(
+        &self,
+        binary: &Path,
+        release: Option<InstalledRelease>,
+    ) -> Result<()> {
+        let Some(path) = &self.installed_path else {
+            return Ok(());
+        };
+        let mut installed = self.read_installed().await?;
+        let key = binary.display().to_string();
+        match release {
+            Some(release) => installed.insert(key, release),
+            None => installed.remove(&key),
+        };
+
+        let content = serde_json::to_string_pretty(&installed).into_diagnostic()?;
+        write_atomically(path, content.as_bytes()).await
+    }
+
+    fn client(&self, options: &ReleaseOptions) -> Result<ReleaseClient> {
+        let forge = options.forge.unwrap_or_default();
+        let base_url = match (&options.api_url, forge) {
+            (Some(url), _) => url.clone(),
+            (None, Forge::GitHub) => api::GITHUB_API_URL.to_string(),
+            (None, Forge::Gitea) => {
+                miette::bail!(
+                    "`api_url` is required for Gitea, e.g. https://gitea.example.com/api/v1"
+                )
+            }
+        };
+        Ok(ReleaseClient::new(forge, base_url))
+    }
+
+    /// Asset pattern of `options` with its placeholders filled in.
+    fn asset_pattern(&self, options: &ReleaseOptions) -> String {
+        options
+            .asset
+            .replace("{os}", self.os)
+            .replace("{arch}", self.arch)
+    }
+
+    /// Picks the single asset of `release` matching the asset pattern of `options`.
+    fn select_asset<'a>(
+        &self,
+        options: &ReleaseOptions,
+        release: &'a api::Release,
+    ) -> Result<&'a api::Asset> {
+        let pattern = self.asset_pattern(options);
+        let matcher = Glob::new(&pattern).into_diagnostic()?.compile_matcher();
+        let mut assets: Vec<_> = release
+            .assets
+            .iter()
+            .filter(|asset| matcher.is_match(&asset.name))
+            .collect();
+
+        match assets.len() {
+            0 => Err(asset_not_found(options, release, pattern).into()),
+            1 => Ok(assets.remove(0)),
+            _ => Err(AmbiguousReleaseAsset {
+                repo: options.repo.clone(),
+                tag: release.tag_name.clone(),
+                pattern,
+                matched: assets.iter().map(|asset| asset.name.clone()).collect(),
+            }
+            .into()),
+        }
+    }
+
+    /// Downloads the asset of `options` and checks it against `sha256`, `checksums` and `signature`.
+    ///
+    /// Returns the release's tag, the asset's name and its content.
+    async fn fetch(&self, options: &ReleaseOptions) -> Result<(String, String, Vec<u8>)> {
+        let client = self.client(options)?;
+        let release = client.release(&options.repo, &options.selector()).await?;
+        let asset = self.select_asset(options, &release)?;
+
+        // Downloads are cached, so that `verify` and `install` download an asset only once
+        let cached = self.cache_dir.as_ref().map(|dir| {
+            dir.join(&options.repo)
+                .join(&release.tag_name)
+                .join(&asset.name)
+        });
+        let content = match &cached {
+            Some(path) if path.exists() => tokio::fs::read(path).await.into_diagnostic()?,
+            _ => {
+                info!(
+                    "Downloading {} {}",
+                    asset.name.blue().bold(),
+                    release.tag_name.dimmed()
+                );
+                let content = client.download(asset).await?;
+                if let Some(path) = &cached {
+                    write_atomically(path, &content).await?;
+                }
+                content
+            }
+        };
+
+        if let Err(error) = self
+            .check(&client, options, &release, asset, &content)
+            .await
+        {
+            // Dropped, so that a corrupted download is not reused
+            if let Some(path) = &cached {
+                tokio::fs::remove_file(path).await.ok();
+            }
+            return Err(error);
+        }
+
+        Ok((release.tag_name.clone(), asset.name.clone(), content))
+    }
+
+    /// Checks `content` of `asset` against the pins of `options`.
+    async fn check(
+        &self,
+        client: &ReleaseClient,
+        options: &ReleaseOptions,
+        release: &api::Release,
+        asset: &api::Asset,
+        content: &[u8],
+    ) -> Result<()> {
+        if let Some(sha256) = &options.sha256 {
+            verify::check_sha256(&asset.name, content, sha256)?;
+        }
+
+        let checksums = match &options.checksums {
+            Some(name) => {
+                let file = named_asset(options, release, name, asset)?;
+                let checksums = client.download(file).await?;
+                let Some(digest) = std::str::from_utf8(&checksums)
+                    .ok()
+                    .and_then(|checksums| verify::find_checksum(checksums, &asset.name))
+                else {
+                    return Err(VerificationFailed {
+                        artifact: asset.name.clone(),
+                        reason: format!("{} has no checksum of it", file.name),
+                    }
+                    .into());
+                };
+                verify::check_sha256(&asset.name, content, digest)?;
+                Some((file.name.as_str(), checksums))
+            }
+            None => None,
+        };
+
+        let Some((signature, key)) =
+            verify::signature_and_key(&asset.name, &options.signature, &options.key)?
+        else {
+            return Ok(());
+        };
+
+        // Most releases only sign their checksum file, which covers every asset
+        let (signed, content) = match &checksums {
+            Some((name, checksums)) => (*name, checksums.as_slice()),
+            None => (asset.name.as_str(), content),
+        };
+        let signature = named_asset(options, release, signature, asset)?;
+        let signature = client.download(signature).await?;
+        self.context.keys.verify(signed, content, &signature, key)?;
+        Ok(())
+    }
+
+    fn install_path(&self, options: &ReleaseOptions) -> PathBuf {
+        let dir = options
+            .install_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("~/.local/bin"));
+        let binary = options.binary();
+        let binary = if cfg!(windows) {
+            format!("{binary}.exe")
+        } else {
+            binary.to_string()
+        };
+        self.context.resolve_path(&dir).join(binary)
+    }
+}
+
+/// Asset of `release` called `name`, where `{asset}` stands for the name of `asset`.
+fn named_asset<'a>(
+    options: &ReleaseOptions,
+    release: &'a api::Release,
+    name: &str,
+    asset: &api::Asset,
+) -> Result<&'a api::Asset> {
+    let name = name.replace("{asset}", &asset.name);
+    release
+        .assets
+        .iter()
+        .find(|candidate| candidate.name == name)
+        .ok_or_else(|| asset_not_found(options, release, name).into())
+}
+
+fn asset_not_found(
+    options: &ReleaseOptions,
+    release: &api::Release,
+    pattern: String,
+) -> ReleaseAssetNotFound {
+    let names: Vec<_> = release.assets.iter().map(|a| a.name.as_str()).collect();
+    ReleaseAssetNotFound {
+        repo: options.repo.clone(),
+        tag: release.tag_name.clone(),
+        pattern,
+        available: format!("Available assets: {}", names.join(", ")),
+    }
+}
+
+/// Unknown keys are rejected, so that a misspelled pin doesn't install the asset unverified.
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ReleaseOptions {
+    /// Repository in `owner/name` form, e.g. `BurntSushi/ripgrep`.
+    pub repo: String,
+
+    /// Forge hosting the repository, `github` by default.
+    pub forge: Option<Forge>,
+
+    /// Base URL of the forge's API, e.g. `https://gitea.example.com/api/v1`.
+    ///
+    /// Defaults to `https://api.github.com` for GitHub and is required for Gitea.
+    /// Tokens are read from `GITHUB_TOKEN` or `GITEA_TOKEN` if set.
+    pub api_url: Option<String>,
+
+    /// Tag of the release to install, e.g. `14.1.1`.
+    pub tag: Option<String>,
+
+    /// Requirement the release's version has to meet, e.g. `"^14"`, ignoring `v` prefixes of tags.
+    ///
+    /// Without `tag` or `version`, the latest release is installed once and not updated afterwards.
+    #[schemars(with = "Option<String>")]
+    pub version: Option<semver::VersionReq>,
+
+    /// Glob matching the name of the asset to download, with `{os}` and `{arch}` placeholders.
+    ///
+    /// `{os}` is `linux`, `darwin` or `windows` and `{arch}` is e.g. `x86_64` or `aarch64`, e.g.:
+    /// - `"ripgrep-*-{arch}-unknown-{os}-musl.tar.gz"`
+    ///
+    /// `.tar.gz`, `.tar.zst` and `.zip` assets are extracted, other assets are the binary itself.
+    pub asset: String,
+
+    /// Name of the executable inside the asset, the repository name by default.
+    pub binary: Option<String>,
+
+    /// Directory the binary is installed into, relative to the configuration file.
+    /// Defaults to `~/.local/bin`.
+    pub install_dir: Option<PathBuf>,
+
+    /// Expected SHA-256 digest of the asset in hex, as printed by `sha256sum`.
+    pub sha256: Option<String>,
+
+    /// Name of an asset listing checksums of the others, e.g. `SHA256SUMS` or `{asset}.sha256`,
+    /// where `{asset}` is the name of the downloaded asset.
+    pub checksums: Option<String>,
+
+    /// Name of an asset with a detached minisign or SSH signature, e.g. `{asset}.minisig`.
+    ///
+    /// If `checksums` is set, the signature has to be one of the checksum file instead,
+    /// e.g. `SHA256SUMS.sig`.
+    pub signature: Option<String>,
+
+    /// Name of the key under `keys` that `signature` has to be made with.
+    pub key: Option<String>,
+}
+
+impl ReleaseOptions {
+    fn is_pinned(&self) -> bool {
+        self.sha256.is_some()
+            || self.checksums.is_some()
+            || self.signature.is_some()
+            || self.key.is_some()
+    }
+
+    fn binary(&self) -> &str {
+        self.binary
+            .as_deref()
+            .unwrap_or_else(|| self.repo.rsplit('/').next().unwrap_or(&self.repo))
+    }
+
+    fn selector(&self) -> Selector<'_> {
+        match (&self.tag, &self.version) {
+            (Some(tag), _) => Selector::Tag(tag),
+            (None, Some(version)) => Selector::Version(version),
+            (None, None) => Selector::Latest,
+        }
+    }
+
+    /// Whether an installed release with `tag` is the one `tag` or `version` ask for.
+    fn accepts(&self, tag: &str) -> bool {
+        match self.selector() {
+            Selector::Tag(wanted) => wanted == tag,
+            Selector::Version(requirement) => {
+                tag_version(tag).is_some_and(|version| requirement.matches(&version))
+            }
+            Selector::Latest => true,
+        }
+    }
+}
+
+#[async_trait]
+impl PackageManager for Release {
+    const NAME: &'static str = "release";
+    const SUPPORTED_OS: &'static [OsName] = &[OsName::Linux, OsName::MacOS, OsName::Windows];
+
+    type Options = ReleaseOptions;
+    type Package = InstalledRelease;
+
+    async fn get_installed(
+        &self,
+        options: &Self::Options,
+    ) -> Result<HashMap<String, Self::Package>> {
+        let path = self.install_path(options);
+        let installed = self
+            .read_installed()
+            .await?
+            .remove(&path.display().to_string())
+            // Binaries removed by hand are installed again
+            .filter(|release| release.repo == options.repo && path.exists())
+            .map(|release| (options.repo.clone(), release));
+        Ok(installed.into_iter().collect())
+    }
+
+    fn filter_missing(
+        &self,
+        installed: HashMap<String, Self::Package>,
+        desired: &Self::Options,
+    ) -> Result<(Self::Options, usize)> {
+        let satisfied = installed
+            .get(&desired.repo)
+            .is_some_and(|release| desired.accepts(&release.tag));
+        Ok((desired.clone(), usize::from(!satisfied)))
+    }
+
+    fn packages(&self, options: &Self::Options) -> Vec<String> {
+        vec![options.repo.clone()]
+    }
+
+    fn version(&self, package: &Self::Package) -> Option<String> {
+        Some(package.tag.clone())
+    }
+
+    /// Only a `tag` is known before installing, `version` requirements are resolved by the forge.
+    async fn candidate_versions(
+        &self,
+        missing: &Self::Options,
+    ) -> Result<Vec<(String, Option<String>)>> {
+        Ok(vec![(missing.repo.clone(), missing.tag.clone())])
+    }
+
+    fn commands(&self, options: &Self::Options) -> Vec<String> {
+        vec![format!(
+            "download {} ({}) of {} to {}",
+            self.asset_pattern(options),
+            options.selector(),
+            options.repo,
+            self.install_path(options).display()
+        )]
+    }
+
+    async fn verify(&self, options: &Self::Options) -> Result<()> {
+        if options.is_pinned() {
+            self.fetch(options).await?;
+        }
+        Ok(())
+    }
+
+    async fn install(&self, options: Self::Options) -> Result<()> {
+        let (release, asset, content) = self.fetch(&options).await?;
+        let binary = archive::extract_binary(&asset, content, options.binary())?;
+
+        let path = self.install_path(&options);
+        // Replaced at once, so that a running binary is never overwritten in place
+        write_atomically(&path, &binary).await?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let permissions = std::fs::Permissions::from_mode(0o755);
+            tokio::fs::set_permissions(&path, permissions)
+                .await
+                .into_diagnostic()?;
+        }
+
+        let installed = InstalledRelease {
+            repo: options.repo.clone(),
+            tag: release,
+            path: path.clone(),
+        };
+        self.write_installed(&path, Some(installed)).await
+    }
+
+    async fn snapshot(&self, options: &Self::Options) -> Result<Option<HashMap<String, String>>> {
+        let snapshot = self
+            .get_installed(options)
+            .await?
+            .into_iter()
+            .map(|(repo, release)| (repo, release.tag))
+            .collect();
+        Ok(Some(snapshot))
+    }
+
+    /// Deletes installed binaries, and installs the previous release again for upgraded ones.
+    async fn rollback(&self, options: &Self::Options, changes: &[Change]) -> Result<()> {
+        for change in changes {
+            match change {
+                Change::Installed { .. } => {
+                    let path = self.install_path(options);
+                    info!("Removing {}", path.display().blue().bold());
+                    match tokio::fs::remove_file(&path).await {
+                        Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                            return Err(error).into_diagnostic();
+                        }
+                        _ => {}
+                    }
+                    self.write_installed(&path, None).await?;
+                }
+                Change::Upgraded { from, .. } => {
+                    // `sha256` pins the asset of the newer release, `checksums` and `signature` still apply
+                    let previous = ReleaseOptions {
+                        tag: Some(from.clone()),
+                        version: None,
+                        sha256: None,
+                        ..options.clone()
+                    };
+                    self.install(previous).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use wiremock::{Mock, MockServer, ResponseTemplate, matchers::path};
+
+    use super::*;
+    use crate::{
+        errors::LockDeviation,
+        lockfile::{self, LockedProfile, Lockfile},
+        privilege::Privileges,
+        runner::RecordingRunner,
+        transactions,
+        verify::{Key, Keys},
+    };
+
+    const SSH_KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIETtSY7MRPJtDyC1JD9gE/KUDPcsl61cwZl6LjH8qA3T";
+
+    fn release(dir: &std::path::Path) -> Release {
+        let keys = HashMap::from([("laptop".to_string(), Key::Ssh(SSH_KEY.to_string()))]);
+        let context = Context {
+            privileges: Privileges::Root,
+            interactive: false,
+            runner: Arc::new(RecordingRunner::default()),
+            config_dir: dir.to_path_buf(),
+            keys: Keys::new(keys),
+        };
+        Release {
+            context,
+            os: "linux",
+            arch: "x86_64",
+            installed_path: Some(dir.join("state").join("releases.json")),
+            cache_dir: Some(dir.join("cache")),
+        }
+    }
+
+    fn options(yaml: &str) -> ReleaseOptions {
+        serde_yaml::from_str(yaml).expect("Failed to parse options")
+    }
+
+    #[tokio::test]
+    async fn installs_matching_release_asset() {
+        let server = MockServer::start().await;
+        let asset = |name: &str| {
+            serde_json::json!({
+                "name": name,
+                "browser_download_url": format!("{}/download/{name}", server.uri()),
+            })
+        };
+        Mock::given(path("/repos/sharkdp/fd/releases"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "tag_name": "v11.0.0", "prerelease": true, "assets": [] },
+                {
+                    "tag_name": "v10.2.0",
+                    "assets": [asset("fd-x86_64-linux"), asset("fd-aarch64-linux")],
+                },
+                { "tag_name": "v9.0.0", "assets": [] },
+            ])))
+            .mount(&server)
+            .await;
+        Mock::given(path("/download/fd-x86_64-linux"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"\x7fELF".to_vec()))
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let release = release(dir.path());
+        let options = options(&format!(
+            "{{ repo: sharkdp/fd, api_url: '{}', version: '^10', asset: 'fd-{{arch}}-{{os}}', install_dir: bin }}",
+            server.uri()
+        ));
+
+        let (missing, count) = release.find_missing(&options).await.unwrap();
+        assert_eq!(count, 1);
+        release.install(missing).await.unwrap();
+
+        let binary = dir.path().join("bin").join("fd");
+        assert_eq!(std::fs::read(&binary).unwrap(), b"\x7fELF");
+
+        let installed = release.get_installed(&options).await.unwrap();
+        assert_eq!(installed["sharkdp/fd"].tag, "v10.2.0");
+        assert_eq!(release.find_missing(&options).await.unwrap().1, 0);
+
+        // A changed requirement installs another release
+        let newer = ReleaseOptions {
+            version: Some(semver::VersionReq::parse("^11").unwrap()),
+            ..options
+        };
+        assert_eq!(release.find_missing(&newer).await.unwrap().1, 1);
+    }
+
+    #[tokio::test]
+    async fn verifies_signed_checksums() {
+        let server = MockServer::start().await;
+        let asset = |name: &str| {
+            serde_json::json!({
+                "name": name,
+                "browser_download_url": format!("{}/download/{name}", server.uri()),
+            })
+        };
+        Mock::given(path("/repos/sharkdp/fd/releases/tags/v10.2.0"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "tag_name": "v10.2.0",
+                "assets": [asset("fd-x86_64-linux"), asset("SHA256SUMS"), asset("SHA256SUMS.sig")],
+            })))
+            .mount(&server)
+            .await;
+        let files: [(&str, &[u8]); 3] = [
+            ("fd-x86_64-linux", b"\x7fELF"),
+            (
+                "SHA256SUMS",
+                b"3bdbb4fe8397cd2b842430b39ccff01a8663c751945ef5e9a09e267fb8b1d359  fd-x86_64-linux\n",
+            ),
+            (
+                "SHA256SUMS.sig",
+                b"-----BEGIN SSH SIGNATURE-----
+U1NIU0lHAAAAAQAAADMAAAALc3NoLWVkMjU1MTkAAAAgRO1JjsxE8m0PILUkP2AT8pQM9y
+yXrVzBmXouMfyoDdMAAAAEZmlsZQAAAAAAAAAGc2hhNTEyAAAAUwAAAAtzc2gtZWQyNTUx
+OQAAAEBVKowFYuSTpMrLAb+Uqc2+PaPfA5bUbDl1HIaby1e962WGWVWCeKIBn0R2nzP8HV
+4aXW9LygCobWs7V6lC+UAN
+-----END SSH SIGNATURE-----
+",
+            ),
+        ];
+        for (name, content) in files {
+            Mock::given(path(format!("/download/{name}")))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(content.to_vec()))
+                .mount(&server)
+                .await;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let release = release(dir.path());
+        let options = options(&format!(
+            "{{ repo: sharkdp/fd, api_url: '{}', tag: v10.2.0, asset: 'fd-{{arch}}-{{os}}', install_dir: bin,
+                checksums: SHA256SUMS, signature: SHA256SUMS.sig, key: laptop }}",
+            server.uri()
+        ));
+
+        release.verify(&options).await.unwrap();
+        release.install(options.clone()).await.unwrap();
+        assert!(dir.path().join("bin").join("fd").exists());
+
+        let tampered = ReleaseOptions {
+            sha256: Some(verify::sha256(b"tampered")),
+            ..options
+        };
+        let error = release.verify(&tampered).await.unwrap_err();
+        assert!(error.downcast_ref::<VerificationFailed>().is_some());
+        let cached = dir.path().join("cache/sharkdp/fd/v10.2.0/fd-x86_64-linux");
+        assert!(!cached.exists());
+
+        // A misspelled pin must not install the asset unverified
+        let misspelled = "{ repo: sharkdp/fd, checksum: SHA256SUMS }";
+        let error = serde_yaml::from_str::<ReleaseOptions>(misspelled).unwrap_err();
+        assert!(error.to_string().contains("`checksums`"), "{error}");
+    }
+
+    /// Serves `v10.2.0` and `v11.0.0` of `sharkdp/fd`, listed and by tag.
+    async fn serve_fd_releases(server: &MockServer) {
+        let release_json = |tag: &str| {
+            let name = format!("fd-{tag}-x86_64-linux");
+            serde_json::json!({
+                "tag_name": tag,
+                "assets": [{
+                    "name": name,
+                    "browser_download_url": format!("{}/download/{name}", server.uri()),
+                }],
+            })
+        };
+        Mock::given(path("/repos/sharkdp/fd/releases"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                release_json("v11.0.0"),
+                release_json("v10.2.0"),
+            ])))
+            .mount(server)
+            .await;
+        for tag in ["v10.2.0", "v11.0.0"] {
+            Mock::given(path(format!("/repos/sharkdp/fd/releases/tags/{tag}")))
+                .respond_with(ResponseTemplate::new(200).set_body_json(release_json(tag)))
+                .mount(server)
+                .await;
+            Mock::given(path(format!("/download/fd-{tag}-x86_64-linux")))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(tag.as_bytes().to_vec()))
+                .mount(server)
+                .await;
+        }
+    }
+
+    #[tokio::test]
+    async fn checks_installed_and_planned_releases_against_lock() {
+        let server = MockServer::start().await;
+        serve_fd_releases(&server).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let release = release(dir.path());
+        let with = |selector: &str| {
+            options(&format!(
+                "{{ repo: sharkdp/fd, api_url: '{}', {selector}, asset: 'fd-*-{{arch}}-{{os}}', install_dir: bin }}",
+                server.uri()
+            ))
+        };
+        let installed_profile = async |options: &ReleaseOptions| {
+            let mut profile = LockedProfile::new();
+            let versions = release.installed_versions(options).await.unwrap();
+            lockfile::insert(&mut profile, "tools", "release", versions);
+            profile
+        };
+        let planned_profile = async |options: &ReleaseOptions| {
+            let (missing, _) = release.find_missing(options).await.unwrap();
+            let mut profile = installed_profile(options).await;
+            let versions = release.candidate_versions(&missing).await.unwrap();
+            lockfile::overlay(&mut profile, "tools", "release", versions);
+            profile
+        };
+
+        // `chezpilot lock` after installing
+        let options = with("version: '^10'");
+        release.install(options.clone()).await.unwrap();
+        let path = Lockfile::path(&dir.path().join("chezpilot.yaml"));
+        let lock = Lockfile {
+            profiles: [("laptop".to_string(), installed_profile(&options).await)].into(),
+        };
+        lock.write(&path).await.unwrap();
+        let locked = Lockfile::read(&path).await.unwrap().profiles["laptop"].clone();
+        assert_eq!(locked["tools"]["release"]["sharkdp/fd"], "v10.2.0");
+
+        // `chezpilot apply --locked` with nothing to install
+        assert!(lockfile::check("laptop", &locked, &planned_profile(&options).await).is_ok());
+
+        // A tag is known up front, so it deviates before anything is installed
+        let tagged = with("tag: v11.0.0");
+        let error =
+            lockfile::check("laptop", &locked, &planned_profile(&tagged).await).unwrap_err();
+        let [
+            LockDeviation::VersionMismatch {
+                installed: version, ..
+            },
+        ] = &error.deviations[..]
+        else {
+            panic!("Expected a version mismatch, got {:?}", error.deviations);
+        };
+        assert_eq!(version, "v11.0.0");
+
+        // A requirement is only resolved while installing, so it deviates afterwards
+        let newer = with("version: '^11'");
+        assert!(lockfile::check("laptop", &locked, &planned_profile(&newer).await).is_ok());
+        release.install(newer.clone()).await.unwrap();
+        let error =
+            lockfile::check("laptop", &locked, &installed_profile(&newer).await).unwrap_err();
+        let [
+            LockDeviation::VersionMismatch {
+                installed: version, ..
+            },
+        ] = &error.deviations[..]
+        else {
+            panic!("Expected a version mismatch, got {:?}", error.deviations);
+        };
+        assert_eq!(version, "v11.0.0");
+    }
+
+    #[tokio::test]
+    async fn rolls_back_installed_and_upgraded_releases() {
+        let server = MockServer::start().await;
+        serve_fd_releases(&server).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let release = release(dir.path());
+        let with = |selector: &str| {
+            options(&format!(
+                "{{ repo: sharkdp/fd, api_url: '{}', {selector}, asset: 'fd-*-{{arch}}-{{os}}', install_dir: bin }}",
+                server.uri()
+            ))
+        };
+        let install = async |options: ReleaseOptions| {
+            let before = release.snapshot(&options).await.unwrap().unwrap();
+            release.install(options.clone()).await.unwrap();
+            let after = release.snapshot(&options).await.unwrap().unwrap();
+            transactions::changes(&before, &after)
+        };
+        let binary = dir.path().join("bin").join("fd");
+
+        let older = with("version: '^10'");
+        let changes = install(older.clone()).await;
+        release.rollback(&older, &changes).await.unwrap();
+        assert!(!binary.exists());
+        assert!(release.get_installed(&older).await.unwrap().is_empty());
+
+        install(older).await;
+        let newer = with("version: '^11'");
+        let changes = install(newer.clone()).await;
+        assert_eq!(std::fs::read(&binary).unwrap(), b"v11.0.0");
+        release.rollback(&newer, &changes).await.unwrap();
+        assert_eq!(std::fs::read(&binary).unwrap(), b"v10.2.0");
+        assert_eq!(
+            release.get_installed(&newer).await.unwrap()["sharkdp/fd"].tag,
+            "v10.2.0"
+        );
+    }
+
+    #[tokio::test]
+    async fn records_binaries_of_one_repository_separately() {
+        let server = MockServer::start().await;
+        serve_fd_releases(&server).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let release = release(dir.path());
+        let with = |binary: &str| {
+            options(&format!(
+                "{{ repo: sharkdp/fd, api_url: '{}', tag: v10.2.0, asset: 'fd-*-{{arch}}-{{os}}', binary: {binary}, install_dir: bin }}",
+                server.uri()
+            ))
+        };
+        let fd = with("fd");
+        let fdfind = with("fdfind");
+
+        release.install(fd.clone()).await.unwrap();
+        release.install(fdfind.clone()).await.unwrap();
+        assert_eq!(release.read_installed().await.unwrap().len(), 2);
+        assert_eq!(release.find_missing(&fd).await.unwrap().1, 0);
+        assert_eq!(release.find_missing(&fdfind).await.unwrap().1, 0);
+
+        std::fs::remove_file(dir.path().join("bin").join("fd")).unwrap();
+        assert_eq!(release.find_missing(&fd).await.unwrap().1, 1);
+        assert_eq!(release.find_missing(&fdfind).await.unwrap().1, 0);
+    }
+}
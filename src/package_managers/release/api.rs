@@ -0,0 +1,178 @@
+use miette::{Context, IntoDiagnostic, Result};
+use reqwest::StatusCode;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ReleaseNotFound;
+
+pub const GITHUB_API_URL: &str = "https://api.github.com";
+
+/// Forge hosting a repository, GitHub and Gitea share the shape of their release API.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Forge {
+    #[default]
+    GitHub,
+    Gitea,
+}
+
+impl Forge {
+    /// Environment variable holding an access token, used to raise rate limits and see private releases.
+    fn token_variable(self) -> &'static str {
+        match self {
+            Forge::GitHub => "GITHUB_TOKEN",
+            Forge::Gitea => "GITEA_TOKEN",
+        }
+    }
+
+    /// Query listing as many releases per page as the forge allows.
+    fn page_query(self) -> (&'static str, &'static str) {
+        match self {
+            Forge::GitHub => ("per_page", "100"),
+            Forge::Gitea => ("limit", "50"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub prerelease: bool,
+    pub assets: Vec<Asset>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Asset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+/// Which release of a repository to install.
+#[derive(Debug, Clone)]
+pub enum Selector<'a> {
+    Tag(&'a str),
+    Version(&'a semver::VersionReq),
+    Latest,
+}
+
+impl std::fmt::Display for Selector<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Selector::Tag(tag) => write!(f, "tag {tag}"),
+            Selector::Version(version) => write!(f, "version {version}"),
+            Selector::Latest => write!(f, "latest"),
+        }
+    }
+}
+
+/// Version of a release tag, e.g. `1.2.3` for `v1.2.3`.
+pub fn tag_version(tag: &str) -> Option<semver::Version> {
+    semver::Version::parse(tag.strip_prefix('v').unwrap_or(tag)).ok()
+}
+
+/// Client of the release API of a GitHub or Gitea instance.
+#[derive(Debug, Clone)]
+pub struct ReleaseClient {
+    base_url: String,
+    forge: Forge,
+    client: reqwest::Client,
+}
+
+impl ReleaseClient {
+    pub fn new(forge: Forge, base_url: impl Into<String>) -> Self {
+        // GitHub rejects API requests without a user agent
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("chezpilot/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .unwrap_or_default();
+
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            forge,
+            client,
+        }
+    }
+
+    fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        let request = self.client.get(url);
+        match std::env::var(self.forge.token_variable()) {
+            Ok(token) => request.header("Authorization", format!("token {token}")),
+            Err(_) => request,
+        }
+    }
+
+    /// Finds the release of `repo` picked by `selector`, skipping drafts and pre-releases.
+    pub async fn release(&self, repo: &str, selector: &Selector<'_>) -> Result<Release> {
+        let not_found = || ReleaseNotFound {
+            repo: repo.to_string(),
+            selector: selector.to_string(),
+        };
+        let url = format!("{}/repos/{repo}/releases", self.base_url);
+
+        let release = match selector {
+            Selector::Tag(tag) => self.fetch(&format!("{url}/tags/{tag}"), &[]).await?,
+            Selector::Latest => self.fetch(&format!("{url}/latest"), &[]).await?,
+            Selector::Version(requirement) => {
+                let releases: Option<Vec<Release>> =
+                    self.fetch(&url, &[self.forge.page_query()]).await?;
+                releases.and_then(|releases| {
+                    releases
+                        .into_iter()
+                        .filter(|release| !release.draft && !release.prerelease)
+                        .filter_map(|release| Some((tag_version(&release.tag_name)?, release)))
+                        .filter(|(version, _)| requirement.matches(version))
+                        .max_by(|(a, _), (b, _)| a.cmp(b))
+                        .map(|(_, release)| release)
+                })
+            }
+        };
+
+        release.ok_or_else(|| not_found().into())
+    }
+
+    /// Fetches `url` as JSON, `None` if it doesn't exist.
+    async fn fetch<T: for<'de> Deserialize<'de>>(
+        &self,
+        url: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Option<T>> {
+        let response = self
+            .get(url)
+            .query(query)
+            .send()
+            .await
+            .into_diagnostic()
+            .wrap_err("Failed to query releases")?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let body = response
+            .error_for_status()
+            .into_diagnostic()
+            .wrap_err("Failed to query releases")?
+            .json()
+            .await
+            .into_diagnostic()
+            .wrap_err("Failed to parse the release response")?;
+        Ok(Some(body))
+    }
+
+    /// Downloads the content of `asset`.
+    pub async fn download(&self, asset: &Asset) -> Result<Vec<u8>> {
+        let bytes = self
+            .get(&asset.browser_download_url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to download {}", asset.name))?
+            .bytes()
+            .await
+            .into_diagnostic()?;
+        Ok(bytes.to_vec())
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use crate::errors::InvalidReleaseArchive;
+
+/// Reads `binary` from the downloaded `asset`, extracting it from an archive if needed.
+///
+/// Files in archives are matched by name, so that versioned top-level directories don't matter.
+/// Assets that aren't archives are the binary itself.
+pub fn extract_binary(
+    asset: &str,
+    content: Vec<u8>,
+    binary: &str,
+) -> Result<Vec<u8>, InvalidReleaseArchive> {
+    let invalid = |reason: String| InvalidReleaseArchive {
+        asset: asset.to_string(),
+        binary: binary.to_string(),
+        reason,
+    };
+
+    let found = if asset.ends_with(".tar.gz") || asset.ends_with(".tgz") {
+        let decoder = flate2::read::GzDecoder::new(Cursor::new(content));
+        from_tar(decoder, binary).map_err(|e| invalid(e.to_string()))?
+    } else if asset.ends_with(".tar.zst") || asset.ends_with(".tzst") {
+        let decoder =
+            zstd::Decoder::new(Cursor::new(content)).map_err(|e| invalid(e.to_string()))?;
+        from_tar(decoder, binary).map_err(|e| invalid(e.to_string()))?
+    } else if asset.ends_with(".zip") {
+        from_zip(content, binary).map_err(invalid)?
+    } else {
+        return Ok(content);
+    };
+
+    found.ok_or_else(|| invalid("no such file in the archive".to_string()))
+}
+
+/// Whether an archive entry at `path` is `binary`, also accepting `binary.exe` on Windows.
+fn is_binary(path: &Path, binary: &str) -> bool {
+    path.file_name().is_some_and(|name| {
+        name == binary || (cfg!(windows) && name.to_string_lossy() == format!("{binary}.exe"))
+    })
+}
+
+fn from_tar(reader: impl Read, binary: &str) -> std::io::Result<Option<Vec<u8>>> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_file() && is_binary(&entry.path()?, binary) {
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            return Ok(Some(content));
+        }
+    }
+    Ok(None)
+}
+
+fn from_zip(content: Vec<u8>, binary: &str) -> Result<Option<Vec<u8>>, String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(content)).map_err(|e| e.to_string())?;
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index).map_err(|e| e.to_string())?;
+        let matches = file
+            .enclosed_name()
+            .is_some_and(|path| file.is_file() && is_binary(&path, binary));
+        if matches {
+            let mut content = Vec::new();
+            file.read_to_end(&mut content).map_err(|e| e.to_string())?;
+            return Ok(Some(content));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn extracts_binary_from_tar_gz() {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o755);
+        header.set_cksum();
+
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        let mut builder = tar::Builder::new(encoder);
+        builder
+            .append_data(&mut header, "ripgrep-14.1.1-x86_64/rg", &b"\x7fELF"[..])
+            .unwrap();
+        let archive = builder.into_inner().unwrap().finish().unwrap();
+
+        let binary = extract_binary("ripgrep-14.1.1-x86_64.tar.gz", archive, "rg").unwrap();
+        assert_eq!(binary, b"\x7fELF");
+    }
+
+    #[test]
+    fn extracts_binary_from_zip() {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file("fd-v10.2.0/fd", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"\x7fELF").unwrap();
+        let archive = writer.finish().unwrap().into_inner();
+
+        let binary = extract_binary("fd-v10.2.0.zip", archive.clone(), "fd").unwrap();
+        assert_eq!(binary, b"\x7fELF");
+
+        let error = extract_binary("fd-v10.2.0.zip", archive, "fdfind").unwrap_err();
+        assert_eq!(error.reason, "no such file in the archive");
+    }
+}
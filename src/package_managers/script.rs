@@ -0,0 +1,417 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+use async_trait::async_trait;
+use miette::{IntoDiagnostic, Result};
+use owo_colors::OwoColorize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    config::OsName,
+    errors::{InvalidScriptSource, PackageProblem},
+    package_managers::{Context, PackageManager},
+    runner::Invocation,
+    utils::cache_dir,
+    verify::{self, Pin},
+};
+
+/// Runs installer scripts that are not packaged anywhere, like `curl | sh` installers.
+pub struct Script {
+    context: Context,
+}
+
+impl Script {
+    pub fn new(context: Context) -> Result<Self> {
+        Ok(Self { context })
+    }
+
+    /// Runs the shell of `options` in its working directory and environment.
+    fn shell(&self, options: &ScriptOptions) -> Invocation {
+        let dir = options.cwd.as_deref().map_or_else(
+            || self.context.config_dir.clone(),
+            |cwd| self.context.resolve_path(cwd),
+        );
+
+        let mut invocation = Invocation::new(options.shell()).current_dir(dir);
+        for (key, value) in options.env.iter().flatten() {
+            invocation = invocation.env(key, value);
+        }
+        invocation
+    }
+
+    /// Runs `command` with the shell, working directory and environment of `options`.
+    fn invocation(&self, options: &ScriptOptions, command: &str) -> Invocation {
+        self.shell(options).args(["-c", command])
+    }
+
+    /// Downloads the script at `url` and checks it against the pin of `options`.
+    async fn download(&self, options: &ScriptOptions, url: &str) -> Result<Vec<u8>> {
+        let content = verify::fetch(&self.context, url).await?;
+        options.pin().check(&self.context, url, &content).await?;
+        Ok(content)
+    }
+
+    /// Downloads, verifies and saves the script at `url`, so that it can be run with the shell.
+    async fn save(&self, options: &ScriptOptions, url: &str) -> Result<PathBuf> {
+        let content = self.download(options, url).await?;
+        let Some(dir) = cache_dir() else {
+            miette::bail!("Unable to find a cache directory to download {url} into");
+        };
+
+        // Named by content, so that a script never runs under the name of another one
+        let dir = dir.join("scripts");
+        let path = dir.join(verify::sha256(&content));
+        tokio::fs::create_dir_all(&dir).await.into_diagnostic()?;
+        tokio::fs::write(&path, content).await.into_diagnostic()?;
+        Ok(path)
+    }
+
+    /// Whether `options` is satisfied already, according to `creates` and `check`.
+    ///
+    /// Scripts without either are never satisfied and run every time.
+    async fn satisfied(&self, options: &ScriptOptions) -> Result<bool> {
+        if options.creates.is_none() && options.check.is_none() {
+            return Ok(false);
+        }
+
+        if let Some(creates) = &options.creates
+            && !self.context.resolve_path(creates).exists()
+        {
+            return Ok(false);
+        }
+
+        if let Some(check) = &options.check {
+            // Checks like `command -v rustup` print on success, which is of no interest here
+            let output = self
+                .context
+                .runner
+                .output(&self.invocation(options, check))
+                .await
+                .into_diagnostic()?;
+            return Ok(output.status.success());
+        }
+
+        Ok(true)
+    }
+}
+
+/// Unknown keys are rejected, so that a misspelled pin doesn't run the script unverified.
+#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptOptions {
+    /// Name shown in plans and status output, the `install` command if not set.
+    pub name: Option<String>,
+
+    /// Command that installs the tool, e.g. `"curl -fsSL https://sh.rustup.rs | sh -s -- -y"`.
+    ///
+    /// Either `install` or `url` has to be set.
+    pub install: Option<String>,
+
+    /// URL of an installer script run with `shell`, e.g. `"https://sh.rustup.rs"`.
+    ///
+    /// Unlike piping it from `curl` in `install`, the script is checked
+    /// against `sha256` and `signature` before it runs.
+    pub url: Option<String>,
+
+    /// Arguments passed to the script downloaded from `url`, e.g. `["-y"]`.
+    pub args: Option<Vec<String>>,
+
+    /// Expected SHA-256 digest of the script downloaded from `url` in hex, as printed by `sha256sum`.
+    pub sha256: Option<String>,
+
+    /// Detached minisign or SSH signature of the script downloaded from `url`,
+    /// either a URL or a path relative to the configuration file.
+    pub signature: Option<String>,
+
+    /// Name of the key under `keys` that `signature` has to be made with.
+    pub key: Option<String>,
+
+    /// Command that exits successfully if the tool is installed already, e.g. `"command -v rustup"`.
+    pub check: Option<String>,
+
+    /// Path that exists once the tool is installed, e.g. `"~/.cargo/bin/rustup"`.
+    ///
+    /// If both `check` and `creates` are set, both have to pass.
+    /// If neither is set, the script runs every time.
+    pub creates: Option<PathBuf>,
+
+    /// Shell commands are run with, as `<shell> -c <command>`, `sh` by default.
+    pub shell: Option<String>,
+
+    /// Working directory, relative to the configuration file. Defaults to its directory.
+    pub cwd: Option<PathBuf>,
+
+    /// Environment variables set for `check` and `install`.
+    pub env: Option<BTreeMap<String, String>>,
+}
+
+impl ScriptOptions {
+    fn name(&self) -> String {
+        self.name
+            .iter()
+            .chain(&self.install)
+            .chain(&self.url)
+            .next()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn shell(&self) -> &str {
+        self.shell.as_deref().unwrap_or("sh")
+    }
+
+    fn pin(&self) -> Pin {
+        Pin {
+            sha256: self.sha256.clone(),
+            signature: self.signature.clone(),
+            key: self.key.clone(),
+        }
+    }
+
+    /// Where the script comes from, failing if not exactly one of `install` and `url` is set.
+    fn source(&self) -> Result<Source<'_>, InvalidScriptSource> {
+        match (&self.install, &self.url) {
+            (Some(command), None) => Ok(Source::Command(command)),
+            (None, Some(url)) => Ok(Source::Url(url)),
+            (install, _) => Err(InvalidScriptSource {
+                name: self.name(),
+                found: if install.is_some() {
+                    "both `install` and `url`"
+                } else {
+                    "neither `install` nor `url`"
+                },
+            }),
+        }
+    }
+}
+
+enum Source<'a> {
+    Command(&'a str),
+    Url(&'a str),
+}
+
+#[async_trait]
+impl PackageManager for Script {
+    const NAME: &'static str = "script";
+    const SUPPORTED_OS: &'static [OsName] = &[OsName::Linux, OsName::MacOS, OsName::Windows];
+
+    type Options = ScriptOptions;
+    /// Scripts have no version or other details to report.
+    type Package = ();
+
+    async fn get_installed(
+        &self,
+        options: &Self::Options,
+    ) -> Result<HashMap<String, Self::Package>> {
+        let mut installed = HashMap::new();
+        if self.satisfied(options).await? {
+            installed.insert(options.name(), ());
+        }
+        Ok(installed)
+    }
+
+    fn filter_missing(
+        &self,
+        installed: HashMap<String, Self::Package>,
+        desired: &Self::Options,
+    ) -> Result<(Self::Options, usize)> {
+        let missing = usize::from(!installed.contains_key(&desired.name()));
+        Ok((desired.clone(), missing))
+    }
+
+    fn packages(&self, options: &Self::Options) -> Vec<String> {
+        vec![options.name()]
+    }
+
+    fn commands(&self, options: &Self::Options) -> Vec<String> {
+        let command = match (&options.install, &options.url) {
+            (Some(command), _) => command.clone(),
+            (None, Some(url)) => Invocation::new(options.shell())
+                .arg(url)
+                .args(options.args.iter().flatten())
+                .to_string(),
+            (None, None) => String::new(),
+        };
+        vec![command]
+    }
+
+    async fn validate(&self, options: &Self::Options) -> Result<Vec<PackageProblem>> {
+        let problems = options.source().err().map(PackageProblem::from);
+        Ok(problems.into_iter().collect())
+    }
+
+    async fn verify(&self, options: &Self::Options) -> Result<()> {
+        if let Source::Url(url) = options.source()?
+            && !options.pin().is_empty()
+        {
+            self.download(options, url).await?;
+        }
+        Ok(())
+    }
+
+    async fn install(&self, options: Self::Options) -> Result<()> {
+        let invocation = match options.source()? {
+            Source::Command(command) => self.invocation(&options, command),
+            Source::Url(url) => {
+                let script = self.save(&options, url).await?;
+                self.shell(&options)
+                    .arg(script.display().to_string())
+                    .args(options.args.iter().flatten())
+            }
+        };
+
+        info!("Running {}", options.name().blue().bold());
+        let invocation = invocation.stdin(self.context.interactive);
+        let status = self
+            .context
+            .runner
+            .status(&invocation)
+            .await
+            .into_diagnostic()?;
+
+        if !status.success() {
+            miette::bail!("{} exited with status: {}", options.name(), status);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        errors::VerificationFailed, privilege::Privileges, runner::RecordingRunner, verify::Keys,
+    };
+
+    fn script(config_dir: PathBuf) -> (Script, Arc<RecordingRunner>) {
+        let runner = Arc::new(RecordingRunner::default());
+        let context = Context {
+            privileges: Privileges::Root,
+            interactive: false,
+            runner: runner.clone(),
+            config_dir,
+            keys: Keys::default(),
+        };
+        (Script::new(context).unwrap(), runner)
+    }
+
+    fn options(yaml: &str) -> ScriptOptions {
+        serde_yaml::from_str(yaml).expect("Failed to parse options")
+    }
+
+    #[tokio::test]
+    async fn runs_script_when_check_fails() {
+        let (script, runner) = script(PathBuf::from("/home/user/dotfiles"));
+        runner.script("bash", 1, "");
+
+        let options = options(
+            r#"
+            name: rustup
+            install: curl -fsSL https://sh.rustup.rs | sh -s -- -y
+            check: command -v rustup
+            shell: bash
+            cwd: tools
+            env: { RUSTUP_INIT_SKIP_PATH_CHECK: "yes" }
+            "#,
+        );
+        let (missing, count) = script.find_missing(&options).await.unwrap();
+        assert_eq!(count, 1);
+        script.install(missing).await.unwrap();
+
+        let invocation = |command: &str| {
+            Invocation::new("bash")
+                .args(["-c", command])
+                .current_dir("/home/user/dotfiles/tools")
+                .env("RUSTUP_INIT_SKIP_PATH_CHECK", "yes")
+        };
+        assert_eq!(
+            runner.invocations(),
+            [
+                invocation("command -v rustup"),
+                invocation("curl -fsSL https://sh.rustup.rs | sh -s -- -y"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_script_when_created_path_exists() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("bin")).unwrap();
+        std::fs::write(dir.path().join("bin").join("uv"), "").unwrap();
+        let (script, runner) = script(dir.path().to_path_buf());
+
+        let options = options(
+            "{ install: curl -LsSf https://astral.sh/uv/install.sh | sh, creates: bin/uv }",
+        );
+        let (_, count) = script.find_missing(&options).await.unwrap();
+
+        assert_eq!(count, 0);
+        assert!(runner.invocations().is_empty());
+    }
+
+    #[tokio::test]
+    async fn refuses_downloaded_script_with_wrong_checksum() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers::path};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/install.sh"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("echo tampered"))
+            .mount(&server)
+            .await;
+
+        let (script, runner) = script(PathBuf::from("/home/user/dotfiles"));
+        let options = options(&format!(
+            "{{ name: uv, url: '{}/install.sh', args: [--quiet], sha256: {} }}",
+            server.uri(),
+            verify::sha256(b"echo installed"),
+        ));
+        assert_eq!(
+            script.commands(&options),
+            [format!("sh {}/install.sh --quiet", server.uri())]
+        );
+
+        let error = script.verify(&options).await.unwrap_err();
+        assert!(error.downcast_ref::<VerificationFailed>().is_some());
+        let error = script.install(options).await.unwrap_err();
+        assert!(error.downcast_ref::<VerificationFailed>().is_some());
+        assert!(runner.invocations().is_empty());
+
+        // A misspelled pin must not run the script unverified
+        let misspelled = "{ name: uv, url: 'https://astral.sh/uv/install.sh', sha265: 3a5f }";
+        let error = serde_yaml::from_str::<ScriptOptions>(misspelled).unwrap_err();
+        assert!(error.to_string().contains("`sha256`"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn reports_scripts_without_single_source() {
+        let (script, _) = script(PathBuf::from("/home/user/dotfiles"));
+
+        let valid =
+            options("{ name: uv, install: curl -LsSf https://astral.sh/uv/install.sh | sh }");
+        assert!(script.validate(&valid).await.unwrap().is_empty());
+
+        for (yaml, found) in [
+            (
+                "{ name: uv, install: sh install.sh, url: 'https://astral.sh/uv/install.sh' }",
+                "both `install` and `url`",
+            ),
+            (
+                "{ name: uv, check: command -v uv }",
+                "neither `install` nor `url`",
+            ),
+        ] {
+            let problems = script.validate(&options(yaml)).await.unwrap();
+            let [PackageProblem::InvalidScript(problem)] = &problems[..] else {
+                panic!("Expected an invalid script, got {problems:?}");
+            };
+            assert_eq!(problem.name, "uv");
+            assert_eq!(problem.found, found);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::{env, str::FromStr, sync::Arc, time::Duration};
+
+use miette::{Context, IntoDiagnostic, Result};
+use owo_colors::OwoColorize;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use strum::{Display, EnumString};
+use tokio::{task::JoinHandle, time};
+use tracing::{debug, info};
+
+use crate::{
+    errors::{
+        ElevationPinReason, ElevationToolNotDetected, PasswordRequired, PrivilegesNotAcquired,
+        RequestedElevationToolNotFound,
+    },
+    runner::{CommandRunner, Invocation},
+};
+
+/// Well below the 5 minute default timeout of both sudo and doas.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Tool used to run commands as root.
+#[derive(Deserialize, JsonSchema, EnumString, Display, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ElevationTool {
+    Sudo,
+    Doas,
+    Run0,
+    Pkexec,
+}
+
+impl ElevationTool {
+    /// Tools tried in order when none was requested.
+    const DETECTION_ORDER: &[Self] = &[Self::Sudo, Self::Doas, Self::Run0, Self::Pkexec];
+
+    /// Arguments that make the tool fail instead of asking for a password.
+    fn non_interactive_args(&self) -> &'static [&'static str] {
+        match self {
+            Self::Sudo | Self::Doas => &["-n"],
+            Self::Run0 => &["--no-ask-password"],
+            Self::Pkexec => &["--disable-internal-agent"],
+        }
+    }
+}
+
+/// How commands that need root are executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privileges {
+    /// Already running as root, commands are executed directly.
+    Root,
+    /// Commands are prefixed with an elevation tool.
+    Elevate(ElevationTool),
+    /// No elevation tool was found, so commands that need root fail.
+    Unavailable,
+}
+
+impl Privileges {
+    /// Picks the elevation tool, preferring the CLI flag, then the `ELEVATION_TOOL`
+    /// environment variable, then the config file, and finally the first tool found in `PATH`.
+    pub fn detect(
+        cli_override: Option<ElevationTool>,
+        config_override: Option<ElevationTool>,
+    ) -> Result<Self> {
+        if is_root() {
+            debug!("Running as root, skipping privilege escalation");
+            return Ok(Self::Root);
+        }
+
+        let env_override = match env::var("ELEVATION_TOOL") {
+            Ok(tool) => Some(ElevationTool::from_str(&tool).map_err(|_| {
+                RequestedElevationToolNotFound {
+                    tool: tool.clone(),
+                    reason: ElevationPinReason::Env,
+                }
+            })?),
+            Err(_) => None,
+        };
+
+        let requested = [
+            (cli_override, ElevationPinReason::Flag),
+            (env_override, ElevationPinReason::Env),
+            (config_override, ElevationPinReason::Config),
+        ]
+        .into_iter()
+        .find_map(|(tool, reason)| tool.map(|tool| (tool, reason)));
+
+        if let Some((tool, reason)) = requested {
+            if !in_path(&tool.to_string()) {
+                let error = RequestedElevationToolNotFound {
+                    tool: tool.to_string(),
+                    reason,
+                };
+                return Err(error.into());
+            }
+            return Ok(Self::Elevate(tool));
+        }
+
+        let detected = ElevationTool::DETECTION_ORDER
+            .iter()
+            .find(|tool| in_path(&tool.to_string()));
+
+        Ok(detected.map_or(Self::Unavailable, |tool| Self::Elevate(*tool)))
+    }
+
+    /// Asks for credentials once and keeps them cached until the returned guard is dropped,
+    /// so that long runs don't prompt again or time out halfway through.
+    ///
+    /// When not `interactive`, fails right away if a password would be needed.
+    pub async fn acquire(
+        &self,
+        runner: Arc<dyn CommandRunner>,
+        interactive: bool,
+    ) -> Result<Option<KeepAlive>> {
+        let (tool, validate, refresh): (_, &[&str], &[&str]) = match self {
+            Self::Root => return Ok(None),
+            Self::Unavailable => return Err(ElevationToolNotDetected.into()),
+            Self::Elevate(tool @ ElevationTool::Sudo) => (tool, &["-v"], &["-n", "-v"]),
+            Self::Elevate(tool @ ElevationTool::Doas) => (tool, &["true"], &["-n", "true"]),
+            // run0 and pkexec authenticate every command through polkit and can't cache credentials
+            Self::Elevate(_) => return Ok(None),
+        };
+        let tool = tool.to_string();
+        let refresh = Invocation::new(&tool).args(refresh.iter().copied());
+
+        let status = if interactive {
+            info!("Requesting root privileges using {}", tool.blue().bold());
+            let validate = Invocation::new(&tool)
+                .args(validate.iter().copied())
+                .stdin(true);
+            runner.status(&validate).await
+        } else {
+            runner.status(&refresh).await
+        }
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to execute {tool}"))?;
+
+        if !status.success() {
+            if !interactive {
+                return Err(PasswordRequired { tool }.into());
+            }
+            return Err(PrivilegesNotAcquired { tool }.into());
+        }
+
+        let task = tokio::spawn(async move {
+            let mut interval = time::interval(KEEP_ALIVE_INTERVAL);
+            // The first tick completes immediately, right after validating
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let result = runner.output(&refresh).await;
+                debug!(?result, "Refreshed {tool} credentials");
+            }
+        });
+
+        Ok(Some(KeepAlive(task)))
+    }
+
+    /// Creates an invocation that runs `program` as root.
+    pub fn invocation(&self, program: &str, interactive: bool) -> Result<Invocation> {
+        match self {
+            Self::Root => Ok(Invocation::new(program)),
+            Self::Elevate(tool) => {
+                let mut invocation = Invocation::new(tool.to_string());
+                if !interactive {
+                    invocation = invocation.args(tool.non_interactive_args().iter().copied());
+                }
+                Ok(invocation.arg(program))
+            }
+            Self::Unavailable => Err(ElevationToolNotDetected.into()),
+        }
+    }
+}
+
+/// Refreshes cached credentials in the background until dropped.
+pub struct KeepAlive(JoinHandle<()>);
+
+impl Drop for KeepAlive {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[cfg(unix)]
+fn is_root() -> bool {
+    // SAFETY: `geteuid` has no preconditions and can't fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_root() -> bool {
+    false
+}
+
+fn in_path(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|paths| {
+        env::split_paths(&paths).any(|directory| directory.join(program).is_file())
+    })
+}
@@ -0,0 +1,218 @@
+use std::{fmt, io, path::PathBuf, process::Stdio};
+
+use async_trait::async_trait;
+use tokio::process::Command;
+
+/// A command to run, kept as plain data so that it can be shown to the user or recorded in tests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub program: String,
+    pub args: Vec<String>,
+
+    /// Whether the command may read from the user's terminal.
+    pub stdin: bool,
+
+    /// Working directory, the current one if `None`.
+    pub dir: Option<PathBuf>,
+
+    /// Environment variables set in addition to the inherited ones.
+    pub env: Vec<(String, String)>,
+}
+
+impl Invocation {
+    pub fn new(program: impl Into<String>) -> Self {
+        Self {
+            program: program.into(),
+            args: Vec::new(),
+            stdin: false,
+            dir: None,
+            env: Vec::new(),
+        }
+    }
+
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn stdin(mut self, attach: bool) -> Self {
+        self.stdin = attach;
+        self
+    }
+
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args).stdin(if self.stdin {
+            Stdio::inherit()
+        } else {
+            Stdio::null()
+        });
+        if let Some(dir) = &self.dir {
+            command.current_dir(dir);
+        }
+        command.envs(self.env.iter().map(|(key, value)| (key, value)));
+        command
+    }
+}
+
+impl fmt::Display for Invocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.env {
+            write!(f, "{key}={value} ")?;
+        }
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {arg}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Exit code of a finished command, `None` if it was killed by a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitStatus(pub Option<i32>);
+
+impl ExitStatus {
+    pub fn success(&self) -> bool {
+        self.0 == Some(0)
+    }
+}
+
+impl From<std::process::ExitStatus> for ExitStatus {
+    fn from(status: std::process::ExitStatus) -> Self {
+        Self(status.code())
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(code) => write!(f, "exit code {code}"),
+            None => write!(f, "terminated by a signal"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Runs external commands, so that install paths can be tested without touching the system.
+#[async_trait]
+pub trait CommandRunner: fmt::Debug + Send + Sync {
+    /// Runs the command with its output going straight to the terminal.
+    async fn status(&self, invocation: &Invocation) -> io::Result<ExitStatus>;
+
+    /// Runs the command and captures its output.
+    async fn output(&self, invocation: &Invocation) -> io::Result<Output>;
+}
+
+#[derive(Debug, Default)]
+pub struct SystemRunner;
+
+#[async_trait]
+impl CommandRunner for SystemRunner {
+    async fn status(&self, invocation: &Invocation) -> io::Result<ExitStatus> {
+        let status = invocation
+            .command()
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status()
+            .await?;
+        Ok(status.into())
+    }
+
+    async fn output(&self, invocation: &Invocation) -> io::Result<Output> {
+        let output = invocation.command().output().await?;
+        Ok(Output {
+            status: output.status.into(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+pub use fake::RecordingRunner;
+
+#[cfg(test)]
+mod fake {
+    use std::{
+        collections::{HashMap, VecDeque},
+        sync::Mutex,
+    };
+
+    use super::*;
+
+    /// Records every invocation and answers with scripted results.
+    ///
+    /// Programs without a scripted result succeed with empty output.
+    #[derive(Debug, Default)]
+    pub struct RecordingRunner {
+        invocations: Mutex<Vec<Invocation>>,
+        scripts: Mutex<HashMap<String, VecDeque<Output>>>,
+    }
+
+    impl RecordingRunner {
+        /// Queues the result of the next invocation of `program`.
+        pub fn script(&self, program: &str, code: i32, stdout: &str) {
+            self.scripts
+                .lock()
+                .unwrap()
+                .entry(program.to_string())
+                .or_default()
+                .push_back(Output {
+                    status: ExitStatus(Some(code)),
+                    stdout: stdout.to_string(),
+                    stderr: String::new(),
+                });
+        }
+
+        pub fn invocations(&self) -> Vec<Invocation> {
+            self.invocations.lock().unwrap().clone()
+        }
+
+        fn run(&self, invocation: &Invocation) -> Output {
+            self.invocations.lock().unwrap().push(invocation.clone());
+            self.scripts
+                .lock()
+                .unwrap()
+                .get_mut(&invocation.program)
+                .and_then(VecDeque::pop_front)
+                .unwrap_or(Output {
+                    status: ExitStatus(Some(0)),
+                    stdout: String::new(),
+                    stderr: String::new(),
+                })
+        }
+    }
+
+    #[async_trait]
+    impl CommandRunner for RecordingRunner {
+        async fn status(&self, invocation: &Invocation) -> io::Result<ExitStatus> {
+            Ok(self.run(invocation).status)
+        }
+
+        async fn output(&self, invocation: &Invocation) -> io::Result<Output> {
+            Ok(self.run(invocation))
+        }
+    }
+}
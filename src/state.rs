@@ -0,0 +1,221 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use miette::{IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::{
+    errors::UnableToReadState,
+    transactions::{RunLog, Transaction},
+    utils::{state_dir, write_atomically},
+};
+
+/// A package chezpilot installed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InstalledPackage {
+    /// Group that declared the package.
+    pub group: String,
+
+    /// Configuration file that declared the group.
+    pub config: PathBuf,
+
+    pub installed_at: DateTime<Utc>,
+
+    /// Version right after installing, if the package manager reports one.
+    pub version: Option<String>,
+}
+
+/// Everything chezpilot installed across runs.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct State {
+    /// Installed packages keyed by package manager and then by package name.
+    pub packages: BTreeMap<String, BTreeMap<String, InstalledPackage>>,
+}
+
+impl State {
+    /// Default location of the state file, `$XDG_STATE_HOME/chezpilot/state.json`.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(state_dir()?.join("state.json"))
+    }
+
+    /// Reads the state file at `path`, a missing file is an empty state.
+    pub async fn read(path: &Path) -> Result<Self> {
+        let content = match tokio::fs::read_to_string(path).await {
+            Ok(content) => content,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::default());
+            }
+            Err(error) => {
+                let reason = error.to_string();
+                return Err(UnableToReadState {
+                    path: path.to_path_buf(),
+                    reason,
+                }
+                .into());
+            }
+        };
+
+        serde_json::from_str(&content).map_err(|error| {
+            UnableToReadState {
+                path: path.to_path_buf(),
+                reason: error.to_string(),
+            }
+            .into()
+        })
+    }
+
+    /// Writes the state to `path`, replacing the previous file at once.
+    pub async fn write(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self).into_diagnostic()?;
+        write_atomically(path, content.as_bytes()).await
+    }
+
+    /// Records `packages` with their versions as installed by `group` of `config`.
+    pub fn record(
+        &mut self,
+        manager: &str,
+        group: &str,
+        config: &Path,
+        packages: Vec<(String, Option<String>)>,
+    ) {
+        let installed_at = Utc::now();
+        let entries = self.packages.entry(manager.to_string()).or_default();
+
+        for (name, version) in packages {
+            let package = InstalledPackage {
+                group: group.to_string(),
+                config: config.to_path_buf(),
+                installed_at,
+                version,
+            };
+            entries.insert(name, package);
+        }
+    }
+
+    /// Removes `packages` of `manager`, e.g. after rolling them back.
+    pub fn forget(&mut self, manager: &str, packages: &[&str]) {
+        if let Some(entries) = self.packages.get_mut(manager) {
+            entries.retain(|name, _| !packages.contains(&name.as_str()));
+        }
+    }
+}
+
+/// State file and run log shared by everything installing during a single `apply` run.
+#[derive(Clone)]
+pub struct StateFile {
+    path: Option<PathBuf>,
+
+    /// Configuration file groups are declared in.
+    config: PathBuf,
+    state: Arc<Mutex<State>>,
+
+    run_path: Option<PathBuf>,
+    run: Arc<Mutex<RunLog>>,
+}
+
+impl StateFile {
+    pub async fn open(config: &Path) -> Result<Self> {
+        let path = State::default_path();
+        let state = match &path {
+            Some(path) => State::read(path).await?,
+            None => {
+                warn!("Unable to find a state directory, installed packages are not recorded");
+                State::default()
+            }
+        };
+
+        let run = RunLog::new();
+        let run_path = RunLog::dir().map(|dir| dir.join(run.file_name()));
+
+        Ok(Self {
+            path,
+            config: std::path::absolute(config).unwrap_or_else(|_| config.to_path_buf()),
+            state: Arc::new(Mutex::new(state)),
+            run_path,
+            run: Arc::new(Mutex::new(run)),
+        })
+    }
+
+    /// Records `packages` installed by `group` and writes the state file.
+    ///
+    /// Packages are already installed at this point, so failing to write is only a warning.
+    pub async fn record(
+        &self,
+        manager: &str,
+        group: &str,
+        packages: Vec<(String, Option<String>)>,
+    ) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        // Held while writing, so that concurrent lanes don't overwrite each other's records
+        let mut state = self.state.lock().await;
+        state.record(manager, group, &self.config, packages);
+
+        if let Err(error) = state.write(path).await {
+            warn!("Failed to write {}: {error}", path.display());
+        }
+    }
+
+    /// Adds `transaction` to the log of this run, which is only written once something changed.
+    pub async fn log(&self, transaction: Transaction) {
+        let Some(path) = &self.run_path else {
+            return;
+        };
+        if transaction.changes.is_empty() && transaction.irreversible.is_empty() {
+            return;
+        }
+
+        let mut run = self.run.lock().await;
+        run.transactions.push(transaction);
+
+        if let Err(error) = run.write(path).await {
+            warn!("Failed to write {}: {error}", path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn records_installed_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chezpilot").join("state.json");
+        let config = Path::new("/home/user/dotfiles/dotget.yaml");
+
+        let mut state = State::read(&path).await.unwrap();
+        state.record(
+            "pacman",
+            "base",
+            config,
+            vec![
+                ("git".to_string(), Some("2.47.0-1".to_string())),
+                ("zsh".to_string(), None),
+            ],
+        );
+        state.record(
+            "pacman",
+            "shell",
+            config,
+            vec![("zsh".to_string(), Some("5.9-5".to_string()))],
+        );
+        state.write(&path).await.unwrap();
+
+        let state = State::read(&path).await.unwrap();
+        let pacman = &state.packages["pacman"];
+        assert_eq!(pacman.len(), 2);
+        assert_eq!(pacman["git"].group, "base");
+        assert_eq!(pacman["git"].config, config);
+        assert_eq!(pacman["zsh"].group, "shell");
+        assert_eq!(pacman["zsh"].version.as_deref(), Some("5.9-5"));
+    }
+}
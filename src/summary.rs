@@ -0,0 +1,104 @@
+use miette::{Report, Result};
+use owo_colors::OwoColorize;
+
+pub enum Outcome {
+    /// `remarked` counts installed packages that were only re-marked, e.g. as explicitly installed.
+    Succeeded {
+        installed: usize,
+        remarked: usize,
+    },
+    Skipped {
+        reason: String,
+    },
+    Failed {
+        code: String,
+    },
+}
+
+struct Entry {
+    group: String,
+    step: String,
+    outcome: Outcome,
+}
+
+/// Outcomes of every group and package manager step of a single `apply` run.
+#[derive(Default)]
+pub struct Summary {
+    entries: Vec<Entry>,
+}
+
+impl Summary {
+    pub fn record(&mut self, group: &str, step: &str, outcome: Outcome) {
+        self.entries.push(Entry {
+            group: group.to_string(),
+            step: step.to_string(),
+            outcome,
+        });
+    }
+
+    pub fn fail(&mut self, group: &str, step: &str, error: &Report) {
+        let code = error
+            .code()
+            .map_or_else(|| "unknown".to_string(), |code| code.to_string());
+        self.record(group, step, Outcome::Failed { code });
+    }
+
+    /// Records a failure of `step` if `result` is an error and passes the result through.
+    pub fn track<T>(&mut self, group: &str, step: &str, result: Result<T>) -> Result<T> {
+        if let Err(error) = &result {
+            self.fail(group, step, error);
+        }
+        result
+    }
+
+    pub fn has_failures(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| matches!(entry.outcome, Outcome::Failed { .. }))
+    }
+
+    pub fn print(&self) {
+        let group_width = self
+            .entries
+            .iter()
+            .map(|entry| entry.group.chars().count())
+            .max()
+            .unwrap_or_default();
+        let step_width = self
+            .entries
+            .iter()
+            .map(|entry| entry.step.chars().count())
+            .max()
+            .unwrap_or_default();
+
+        println!("\n{}", "Summary".bold());
+        for entry in &self.entries {
+            let group = format!("{:<group_width$}", entry.group);
+            let step = format!("{:<step_width$}", entry.step);
+            let (status, details) = match &entry.outcome {
+                Outcome::Succeeded {
+                    installed: 0,
+                    remarked: 0,
+                } => ("succeeded".green().to_string(), "up to date".to_string()),
+                Outcome::Succeeded {
+                    installed,
+                    remarked: 0,
+                } => (
+                    "succeeded".green().to_string(),
+                    format!("{installed} installed"),
+                ),
+                Outcome::Succeeded {
+                    installed,
+                    remarked,
+                } => (
+                    "succeeded".green().to_string(),
+                    format!("{installed} installed, {remarked} re-marked"),
+                ),
+                Outcome::Skipped { reason } => ("skipped  ".yellow().to_string(), reason.clone()),
+                Outcome::Failed { code } => ("failed   ".red().to_string(), code.clone()),
+            };
+
+            println!("  {}  {step}  {status}  {}", group.bold(), details.dimmed());
+        }
+    }
+}
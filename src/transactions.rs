@@ -0,0 +1,178 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use miette::{IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    package_managers::PackageManagerConfig,
+    utils::{state_dir, write_atomically},
+};
+
+/// A change an installation made to a single package.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "change", rename_all = "lowercase")]
+pub enum Change {
+    Installed {
+        package: String,
+        version: String,
+    },
+    Upgraded {
+        package: String,
+        from: String,
+        to: String,
+    },
+}
+
+impl Change {
+    pub fn package(&self) -> &str {
+        match self {
+            Change::Installed { package, .. } | Change::Upgraded { package, .. } => package,
+        }
+    }
+}
+
+/// Changes made by a single `PackageManager::install`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Transaction {
+    /// Groups whose packages were installed, more than one if they shared the transaction.
+    pub groups: Vec<String>,
+
+    /// Options the packages were installed with, also used to revert the changes.
+    pub config: PackageManagerConfig,
+    pub changes: Vec<Change>,
+
+    /// Packages installed by a package manager that can't tell what changed, which `rollback` can't undo.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub irreversible: Vec<String>,
+
+    /// Whether `rollback` reverted the changes already, so a retry after a failure skips them.
+    #[serde(default)]
+    pub rolled_back: bool,
+}
+
+/// Transactions of a single `apply` run, in the order they were made.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunLog {
+    pub started_at: DateTime<Utc>,
+    pub transactions: Vec<Transaction>,
+    pub rolled_back_at: Option<DateTime<Utc>>,
+}
+
+impl RunLog {
+    pub fn new() -> Self {
+        Self {
+            started_at: Utc::now(),
+            transactions: Vec::new(),
+            rolled_back_at: None,
+        }
+    }
+
+    /// Directory run logs are kept in, `$XDG_STATE_HOME/chezpilot/runs`.
+    pub fn dir() -> Option<PathBuf> {
+        Some(state_dir()?.join("runs"))
+    }
+
+    /// Name of the log file, which sorts by the start of the run.
+    pub fn file_name(&self) -> String {
+        self.started_at
+            .format("%Y-%m-%dT%H-%M-%S%.6fZ.json")
+            .to_string()
+    }
+
+    /// Reads the log of the most recent run in `dir` that changed anything.
+    pub async fn latest(dir: &Path) -> Result<Option<(PathBuf, Self)>> {
+        let mut entries = match tokio::fs::read_dir(dir).await {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error).into_diagnostic(),
+        };
+
+        let mut latest: Option<PathBuf> = None;
+        while let Some(entry) = entries.next_entry().await.into_diagnostic()? {
+            let path = entry.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+                && latest.as_ref().is_none_or(|latest| path > *latest)
+            {
+                latest = Some(path);
+            }
+        }
+
+        let Some(path) = latest else {
+            return Ok(None);
+        };
+        let content = tokio::fs::read_to_string(&path).await.into_diagnostic()?;
+        let log = serde_json::from_str(&content).into_diagnostic()?;
+        Ok(Some((path, log)))
+    }
+
+    pub async fn write(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self).into_diagnostic()?;
+        write_atomically(path, content.as_bytes()).await
+    }
+}
+
+/// Changes between snapshots of installed package versions taken before and after installing.
+pub fn changes(before: &HashMap<String, String>, after: &HashMap<String, String>) -> Vec<Change> {
+    let mut changes: Vec<Change> = after
+        .iter()
+        .filter_map(|(package, version)| match before.get(package) {
+            None => Some(Change::Installed {
+                package: package.clone(),
+                version: version.clone(),
+            }),
+            Some(previous) if previous != version => Some(Change::Upgraded {
+                package: package.clone(),
+                from: previous.clone(),
+                to: version.clone(),
+            }),
+            Some(_) => None,
+        })
+        .collect();
+
+    // Snapshots are unordered, sorted logs are easier to read
+    changes.sort_by(|a, b| a.package().cmp(b.package()));
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(name, version)| (name.to_string(), version.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn finds_installed_and_upgraded_packages() {
+        let before = snapshot(&[("git", "2.47.0-1"), ("openssl", "3.4.0-1")]);
+        let after = snapshot(&[
+            ("git", "2.47.0-1"),
+            ("openssl", "3.4.1-1"),
+            ("zsh", "5.9-5"),
+        ]);
+
+        assert_eq!(
+            changes(&before, &after),
+            [
+                Change::Upgraded {
+                    package: "openssl".to_string(),
+                    from: "3.4.0-1".to_string(),
+                    to: "3.4.1-1".to_string(),
+                },
+                Change::Installed {
+                    package: "zsh".to_string(),
+                    version: "5.9-5".to_string(),
+                },
+            ]
+        );
+    }
+}
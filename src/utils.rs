@@ -1,6 +1,7 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use indicatif::ProgressStyle;
+use miette::{IntoDiagnostic, Result};
 use owo_colors::OwoColorize;
 
 pub fn make_link(text: &str, url: &str) -> String {
@@ -20,6 +21,49 @@ macro_rules! success {
     };
 }
 
+/// Picks the candidate closest to `name`, if any is similar enough to be a likely typo.
+pub fn suggest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    candidates
+        .into_iter()
+        .map(|candidate| (strsim::jaro_winkler(name, candidate), candidate))
+        .filter(|(similarity, _)| *similarity >= 0.8)
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, candidate)| format!("Did you mean {candidate:?}?"))
+}
+
+/// Cache directory of chezpilot, `$XDG_CACHE_HOME/chezpilot`.
+pub fn cache_dir() -> Option<PathBuf> {
+    let cache = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache.join("chezpilot"))
+}
+
+/// State directory of chezpilot, `$XDG_STATE_HOME/chezpilot`.
+pub fn state_dir() -> Option<PathBuf> {
+    let state = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })?;
+    Some(state.join("chezpilot"))
+}
+
+/// Writes `content` to `path` through a temporary file, creating missing parent directories.
+///
+/// Renaming is atomic, so an interrupted run never leaves a truncated file behind.
+pub async fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await.into_diagnostic()?;
+    }
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".part");
+    tokio::fs::write(&partial, content)
+        .await
+        .into_diagnostic()?;
+    tokio::fs::rename(&partial, path).await.into_diagnostic()
+}
+
 pub fn get_spinner_style() -> ProgressStyle {
     ProgressStyle::with_template("{prefix:.bold.dim}{spinner:.bold.blue} {wide_msg}")
         .unwrap()
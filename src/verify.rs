@@ -0,0 +1,264 @@
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use miette::{Context as _, IntoDiagnostic, Result};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{errors::VerificationFailed, package_managers::Context};
+
+/// Namespace SSH signatures are made in, as in `ssh-keygen -Y sign -n file`.
+const SSH_NAMESPACE: &str = "file";
+
+/// Public key downloaded artifacts can be signed with.
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Key {
+    /// minisign public key, the second line of a `minisign.pub` file, e.g. `RWQf6LRCGA9i53mlYecO4IzT...`.
+    Minisign(String),
+
+    /// OpenSSH public key, e.g. `ssh-ed25519 AAAAC3NzaC1lZDI1NTE5...`.
+    ///
+    /// Signatures have to be made with `ssh-keygen -Y sign -n file`.
+    Ssh(String),
+}
+
+/// Keys declared in the configuration, by name.
+#[derive(Debug, Clone, Default)]
+pub struct Keys(Arc<HashMap<String, Key>>);
+
+impl Keys {
+    pub fn new(keys: HashMap<String, Key>) -> Self {
+        Self(Arc::new(keys))
+    }
+
+    /// Checks that `signature` of `content` was made with the key named `key`.
+    pub fn verify(
+        &self,
+        artifact: &str,
+        content: &[u8],
+        signature: &[u8],
+        key: &str,
+    ) -> Result<(), VerificationFailed> {
+        let failed = |reason: String| VerificationFailed {
+            artifact: artifact.to_string(),
+            reason,
+        };
+        let Some(public_key) = self.0.get(key) else {
+            return Err(failed(format!("no key named {key:?} is declared")));
+        };
+
+        match public_key {
+            Key::Minisign(public_key) => {
+                let public_key = minisign_verify::PublicKey::from_base64(public_key)
+                    .map_err(|e| failed(format!("invalid minisign key {key:?}: {e}")))?;
+                let signature = std::str::from_utf8(signature)
+                    .ok()
+                    .and_then(|signature| minisign_verify::Signature::decode(signature).ok())
+                    .ok_or_else(|| failed("the signature is not a minisign signature".into()))?;
+                public_key
+                    .verify(content, &signature, false)
+                    .map_err(|e| failed(format!("bad minisign signature: {e}")))
+            }
+            Key::Ssh(public_key) => {
+                let public_key = ssh_key::PublicKey::from_openssh(public_key)
+                    .map_err(|e| failed(format!("invalid SSH key {key:?}: {e}")))?;
+                let signature = ssh_key::SshSig::from_pem(signature)
+                    .map_err(|_| failed("the signature is not an SSH signature".into()))?;
+                public_key
+                    .verify(SSH_NAMESPACE, content, &signature)
+                    .map_err(|e| failed(format!("bad SSH signature: {e}")))
+            }
+        }
+    }
+}
+
+/// Checksum and signature a downloaded artifact has to match before it is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pin {
+    /// Expected SHA-256 digest in hex, as printed by `sha256sum`.
+    pub sha256: Option<String>,
+
+    /// Detached minisign or SSH signature of the artifact,
+    /// either a URL or a path relative to the configuration file.
+    pub signature: Option<String>,
+
+    /// Name of the key under `keys` that `signature` has to be made with.
+    pub key: Option<String>,
+}
+
+impl Pin {
+    pub fn is_empty(&self) -> bool {
+        self.sha256.is_none() && self.signature.is_none() && self.key.is_none()
+    }
+
+    /// Checks `content` of `artifact` against this pin, downloading the signature if needed.
+    pub async fn check(&self, context: &Context, artifact: &str, content: &[u8]) -> Result<()> {
+        if let Some(sha256) = &self.sha256 {
+            check_sha256(artifact, content, sha256)?;
+        }
+
+        if let Some((signature, key)) = signature_and_key(artifact, &self.signature, &self.key)? {
+            let signature = fetch(context, signature).await?;
+            context.keys.verify(artifact, content, &signature, key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Pairs a signature with the key to check it with, failing if only one of them is set.
+pub fn signature_and_key<'a>(
+    artifact: &str,
+    signature: &'a Option<String>,
+    key: &'a Option<String>,
+) -> Result<Option<(&'a str, &'a str)>, VerificationFailed> {
+    match (signature, key) {
+        (Some(signature), Some(key)) => Ok(Some((signature, key))),
+        (None, None) => Ok(None),
+        _ => Err(VerificationFailed {
+            artifact: artifact.to_string(),
+            reason: "`signature` and `key` have to be set together".to_string(),
+        }),
+    }
+}
+
+/// Hex-encoded SHA-256 digest of `content`.
+pub fn sha256(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+/// Checks `content` against a hex-encoded SHA-256 digest.
+pub fn check_sha256(
+    artifact: &str,
+    content: &[u8],
+    expected: &str,
+) -> Result<(), VerificationFailed> {
+    let actual = sha256(content);
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        return Ok(());
+    }
+
+    Err(VerificationFailed {
+        artifact: artifact.to_string(),
+        reason: format!("expected SHA-256 {expected}, got {actual}"),
+    })
+}
+
+/// Finds the digest of `name` in a checksum file like `SHA256SUMS`, as written by `sha256sum`.
+///
+/// A file with a single digest and no name, like `fd.tar.gz.sha256` often is, applies to any name.
+pub fn find_checksum<'a>(checksums: &'a str, name: &str) -> Option<&'a str> {
+    let entries: Vec<(&str, Option<&str>)> = checksums
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let digest = fields.next()?;
+            // `sha256sum --binary` marks names with `*`
+            let file = fields
+                .next()
+                .map(|file| file.trim_start_matches('*').trim_start_matches("./"));
+            Some((digest, file))
+        })
+        .collect();
+
+    match entries.as_slice() {
+        [(digest, None)] => Some(digest),
+        _ => entries
+            .iter()
+            .find(|(_, file)| *file == Some(name))
+            .map(|(digest, _)| *digest),
+    }
+}
+
+/// Reads a URL or a path relative to the configuration file.
+pub async fn fetch(context: &Context, location: &str) -> Result<Vec<u8>> {
+    if location.starts_with("http://") || location.starts_with("https://") {
+        let bytes = reqwest::get(location)
+            .await
+            .and_then(|response| response.error_for_status())
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to download {location}"))?
+            .bytes()
+            .await
+            .into_diagnostic()?;
+        return Ok(bytes.to_vec());
+    }
+
+    tokio::fs::read(context.resolve_path(Path::new(location)))
+        .await
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read {location}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINISIGN_KEY: &str = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+    const MINISIGN_SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1633700835\tfile:test\tprehashed
+wLMDjy9FLAuxZ3q4NlEvkgtyhrr0gtTu6KC4KBJdITbbOeAi1zBIYo0v4iTgt8jJpIidRJnp94ABQkJAgAooBQ==";
+
+    const SSH_KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIETtSY7MRPJtDyC1JD9gE/KUDPcsl61cwZl6LjH8qA3T";
+    const SSH_SIGNATURE: &str = "-----BEGIN SSH SIGNATURE-----
+U1NIU0lHAAAAAQAAADMAAAALc3NoLWVkMjU1MTkAAAAgRO1JjsxE8m0PILUkP2AT8pQM9y
+yXrVzBmXouMfyoDdMAAAAEZmlsZQAAAAAAAAAGc2hhNTEyAAAAUwAAAAtzc2gtZWQyNTUx
+OQAAAEA+iTENqqia07gaLF3JX11qO3wf1xJOTtanUgFDdDK7k1+bqTLl62sh+mhEOnLW9v
+vEas4M5uxXwtUkwO+QbfUI
+-----END SSH SIGNATURE-----
+";
+
+    fn keys() -> Keys {
+        Keys::new(HashMap::from([
+            (
+                "release".to_string(),
+                Key::Minisign(MINISIGN_KEY.to_string()),
+            ),
+            ("laptop".to_string(), Key::Ssh(SSH_KEY.to_string())),
+        ]))
+    }
+
+    #[test]
+    fn verifies_minisign_and_ssh_signatures() {
+        let keys = keys();
+        for (signature, key) in [(MINISIGN_SIGNATURE, "release"), (SSH_SIGNATURE, "laptop")] {
+            keys.verify("test", b"test", signature.as_bytes(), key)
+                .unwrap();
+
+            let error = keys
+                .verify("test", b"tampered", signature.as_bytes(), key)
+                .unwrap_err();
+            assert!(error.reason.starts_with("bad "), "{}", error.reason);
+        }
+
+        let error = keys
+            .verify("test", b"test", SSH_SIGNATURE.as_bytes(), "release")
+            .unwrap_err();
+        assert_eq!(error.reason, "the signature is not a minisign signature");
+    }
+
+    #[test]
+    fn checks_sha256_digests() {
+        let digest = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        assert_eq!(sha256(b"test"), digest);
+        check_sha256("test", b"test", &digest.to_uppercase()).unwrap();
+
+        let error = check_sha256("test", b"tampered", digest).unwrap_err();
+        assert!(error.reason.starts_with("expected SHA-256 9f86d08"));
+    }
+
+    #[test]
+    fn finds_checksums_by_name() {
+        let checksums = "\
+            1111  fd-v10.2.0-x86_64-unknown-linux-musl.tar.gz\n\
+            2222 *fd-v10.2.0-aarch64-unknown-linux-musl.tar.gz\n";
+        assert_eq!(
+            find_checksum(checksums, "fd-v10.2.0-aarch64-unknown-linux-musl.tar.gz"),
+            Some("2222")
+        );
+        assert_eq!(find_checksum(checksums, "fd-v10.2.0.zip"), None);
+        assert_eq!(find_checksum("3333\n", "fd.tar.gz"), Some("3333"));
+    }
+}